@@ -0,0 +1,61 @@
+// Time Cache::check and Cache::set on the start candidates of a synthetic track. Their stop sets are
+// nested by the altitude of the start like with the 1000 m rule. Run with `cargo bench --bench cache`.
+
+use std::time::{Duration, Instant};
+
+use flat_projection::FlatPoint;
+use score_rs::cache::{Cache, CacheItem, StopSet};
+
+const FIXES: usize = 20_000;
+const CANDIDATES: usize = 10_000;
+const BEST_DISTANCE: f32 = 1000.0;
+
+fn main() {
+    let mut seed: u32 = 7;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) as f32 / 65_536.0
+    };
+    let mut position = FlatPoint { x: 0.0, y: 0.0 };
+    let mut flat_points = Vec::with_capacity(FIXES);
+    let mut altitudes = Vec::with_capacity(FIXES);
+    for i in 0..FIXES {
+        position.x += 0.1 * (next() - 0.4);
+        position.y += 0.1 * (next() - 0.5);
+        flat_points.push(position);
+        altitudes.push(1500.0 + 1000.0 * (i as f32 / 500.0).sin() + 200.0 * next());
+    }
+
+    let minimum_stop = FIXES / 2;
+    let mut cache = Cache::new();
+    let mut elapsed = Duration::ZERO;
+    for candidate in 0..CANDIDATES {
+        let start = (next() * minimum_stop as f32) as usize;
+        let stops: StopSet = (minimum_stop..FIXES)
+            .filter(|&stop| altitudes[stop] >= altitudes[start] - 1000.0)
+            .collect();
+        if stops.is_empty() {
+            continue;
+        }
+        let mut item = CacheItem {
+            start,
+            max_stop: stops.max().unwrap(),
+            stops,
+            distance: 0.0,
+        };
+        let started = Instant::now();
+        if !cache.check(&mut item, &flat_points, BEST_DISTANCE) {
+            // the candidates are checked by decreasing distance, so the distances of the items decrease
+            let decrease = 0.04 * candidate as f32 / CANDIDATES as f32;
+            item.distance = BEST_DISTANCE * (1.02 - decrease + 0.01 * next());
+        }
+        cache.set(item);
+        elapsed += started.elapsed();
+    }
+    println!(
+        "cache: {:?} for {} candidates, {:?}",
+        elapsed,
+        CANDIDATES,
+        cache.stats()
+    );
+}
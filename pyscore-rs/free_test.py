@@ -1,59 +1,232 @@
-from typing import NamedTuple
 import datetime as dt
+import json
 
+import pytest
 import score_rs
 import numpy as np
 from numpy.testing import assert_almost_equal
 
 
-class Fix(NamedTuple):
-    lon: float
-    lat: float
-    pressure_alt: int
-    time: int
+def seconds_since_midnight(time: dt.time) -> int:
+    return (time.hour * 60 + time.minute) * 60 + time.second
 
 
-def parse_line(line: str) -> Fix:
-    time = ((int(line[1:3]) * 60) + int(line[3:5])) * 60 + int(line[5:7])
-    lat = float(line[7:9]) + (float(line[9:11]) + float(line[11:14]) / 1000.0) / 60.0
-    if line[14] == "S":
-        lat = -lat
+def test_free():
+    release = seconds_since_midnight(dt.time(8, 12, 29))
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc", altitude="pressure")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    res = score_rs.optimize(lon, lat, alt, 6, time=time, start_time=release, with_legs=True)
+    assert_almost_equal(res[1], 1018.54, 2)
+    assert res[0] == [131, 1067, 2978, 4010, 5179, 7181, 8259]
+    legs = res[2]
+    assert len(legs) == 6
+    assert [(leg.start, leg.stop) for leg in legs] == list(zip(res[0], res[0][1:]))
+    assert_almost_equal(sum(leg.distance for leg in legs), res[1], 2)
+    assert all(0.0 <= leg.bearing < 360.0 for leg in legs)
+    assert legs[0].start_altitude == alt[131]
 
-    lon = float(line[15:18]) + (float(line[18:20]) + float(line[20:23]) / 1000.0) / 60.0
-    if line[23] == "W":
-        lon = -lon
+    path, distance = score_rs.optimize(lon, lat, alt, 2, time=time, start_time=release)
+    assert_almost_equal(distance, 804.95, 2)
+    assert path == [1018, 4017, 7932]
 
-    pressure_alt = int(float(line[25:30]))
-    return Fix(lon, lat, pressure_alt, time)
 
+def test_exact():
+    release = seconds_since_midnight(dt.time(8, 12, 29))
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc", altitude="pressure")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    path, distance = score_rs.optimize(lon, lat, alt, 6, time=time, start_time=release, exact=True)
+    assert path == [131, 1067, 2978, 4010, 5179, 7181, 8259]
+    assert_almost_equal(distance, 1018.542, 3)
 
-def read_igc(
-    file_path: str, release: dt.time
-) -> tuple[np.ndarray, np.ndarray, np.ndarray]:
-    lon, lat, pressure_alt = [], [], []
-    release_seconds_since_midnight = (
-        release.hour * 60 + release.minute
-    ) * 60 + release.second
-    with open(file_path, "r") as file:
-        for line in file.read().split("\n"):
-            if line.startswith("B"):
-                fix = parse_line(line)
-                if fix.time < release_seconds_since_midnight:
-                    continue
-                lon.append(fix.lon)
-                lat.append(fix.lat)
-                pressure_alt.append(fix.pressure_alt)
 
-    return np.array(lon), np.array(lat), np.array(pressure_alt)
+def test_distance_model():
+    release = seconds_since_midnight(dt.time(8, 12, 29))
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc", altitude="pressure")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    path, distance = score_rs.optimize(lon, lat, alt, 6, time=time, start_time=release)
+    sphere_path, sphere_distance, legs = score_rs.optimize(
+        lon, lat, alt, 6, time=time, start_time=release, with_legs=True, distance_model="fai_sphere"
+    )
+    assert sphere_path == path
+    assert sphere_distance != distance
+    assert abs(sphere_distance - distance) < 0.005 * distance
+    assert_almost_equal(sum(leg.distance for leg in legs), sphere_distance, 2)
+    with pytest.raises(ValueError):
+        score_rs.optimize(lon, lat, alt, 6, distance_model="mercator")
 
 
-def test_free():
-    release = dt.time(8, 12, 29)
-    data = read_igc("fixtures/2023-06-17_288167.igc", release)
-    res = score_rs.optimize(data[0], data[1], data[2], 6)
-    assert_almost_equal(res[1], 1018.54, 2)
-    assert res[0] == [0, 936, 2847, 3879, 5048, 7050, 8128]
+def test_max_duration():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    res = score_rs.optimize(lon, lat, alt, 2, time=time, max_duration=3600)
+    assert time[res[0][-1]] - time[res[0][0]] <= 3600
+
+    with pytest.raises(ValueError):
+        score_rs.optimize(lon, lat, alt, 2, max_duration=3600)
+
+
+def test_engine_runs():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    noise = np.where(np.arange(len(lon)) < 2000, 900.0, 50.0)
+    res = score_rs.optimize(lon, lat, alt, 2, engine_noise=noise, engine="after_last_run")
+    assert res[0][0] >= 2000
+
+    with pytest.raises(ValueError):
+        score_rs.optimize(lon, lat, alt, 2, engine_noise=noise, engine="sometimes")
+    for level in (-1.0, 70000.0, np.inf):
+        invalid = noise.copy()
+        invalid[5] = level
+        with pytest.raises(ValueError, match="index 5"):
+            score_rs.optimize(lon, lat, alt, 2, engine_noise=invalid, engine="after_last_run")
+
+
+def test_export():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt = flight.longitude, flight.latitude, flight.altitude
+    path, distance = score_rs.optimize(lon, lat, alt, 2)
+    geojson = json.loads(score_rs.to_geojson(lon, lat, alt, path))
+    line, *turnpoints = geojson["features"]
+    assert len(line["geometry"]["coordinates"]) == len(turnpoints) == 3
+    assert_almost_equal(sum(line["properties"]["legs"]), distance, 2)
+    assert "<LineString>" in score_rs.to_kml(lon, lat, alt, path)
+
+    # the legs are measured with the distance model of the optimization
+    path, distance = score_rs.optimize(lon, lat, alt, 2, distance_model="fai_sphere")
+    geojson = json.loads(score_rs.to_geojson(lon, lat, alt, path, distance_model="fai_sphere"))
+    assert_almost_equal(sum(geojson["features"][0]["properties"]["legs"]), distance, 2)
+
+    with pytest.raises(ValueError):
+        score_rs.to_geojson(lon, lat, alt, [len(lon)])
+    with pytest.raises(ValueError):
+        score_rs.to_kml(lon, lat, alt, path, distance_model="euclid")
+
+
+def test_read_igc():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    assert len(flight.longitude) == len(flight.time) == len(flight.enl)
+    assert flight.mop is None
+    assert flight.headers["GTY"] == "LS 10-s-18"
+    assert flight.task[1][2] == "NASTAETTEN"
+
+    with pytest.raises(FileNotFoundError):
+        score_rs.read_igc("fixtures/missing.igc")
+
+    with pytest.raises(ValueError):
+        score_rs.read_igc("fixtures/2023-06-17_288167.igc", altitude="radar")
+
+
+def test_invalid_input_raises():
+    empty = np.array([], dtype=float)
+    with pytest.raises(ValueError):
+        score_rs.optimize(empty, empty, np.array([], dtype=int), 6)
+
+    lon = np.array([10.0, 10.1, np.nan])
+    lat = np.array([50.0, 50.1, 50.2])
+    with pytest.raises(ValueError):
+        score_rs.optimize(lon, lat, np.array([0, 0, 0]), 2)
+
+    with pytest.raises(ValueError):
+        score_rs.optimize(lon[:2], lat, np.array([0, 0, 0]), 2)
+
+
+def test_progress():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    reports = []
+    path, distance = score_rs.optimize(lon, lat, alt, 6, progress=reports.append)
+    assert all(report.pruned + report.built <= report.checked for report in reports)
+    assert all(a.scanned <= b.scanned for a, b in zip(reports, reports[1:]))
+    assert all(report.hits == report.pruned for report in reports)
+    assert all(report.best_distance <= distance + 1.0 for report in reports)
+
+
+def test_memory_budget():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt = flight.longitude, flight.latitude, flight.altitude
+    path, distance = score_rs.optimize(lon, lat, alt, 2)
+    on_the_fly = score_rs.optimize(lon, lat, alt, 2, memory_budget=0)
+    assert tuple(on_the_fly) == (path, distance)
+    results = score_rs.optimize_many([(lon, lat, alt)], 2, memory_budget=0)
+    assert tuple(results[0]) == (path, distance)
+
+
+def test_optimize_many():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    single = score_rs.optimize(lon, lat, alt, 2)
+    empty = np.array([], dtype=float)
+    results = score_rs.optimize_many(
+        [(lon, lat, alt), (lon, lat, alt, time), (empty, empty, np.array([], dtype=int))], 2
+    )
+    assert len(results) == 3
+    assert tuple(results[0]) == tuple(single)
+    assert tuple(results[1]) == tuple(single)
+    assert isinstance(results[2], ValueError)
+
+
+def test_optimize_many_per_flight_settings():
+    release = seconds_since_midnight(dt.time(8, 12, 29))
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    windowed = score_rs.optimize(lon, lat, alt, 2, time=time, start_time=release, distance_model="fai_sphere")
+    noise = np.where(np.arange(len(lon)) < 2000, 900.0, 50.0)
+    engine = score_rs.optimize(
+        lon, lat, alt, 2, time=time, engine_noise=noise, engine="after_last_run", distance_model="fai_sphere"
+    )
+    results = score_rs.optimize_many(
+        [(lon, lat, alt, time), (lon, lat, alt, time)],
+        2,
+        start_time=[release, None],
+        engine_noise=[None, noise],
+        engine="after_last_run",
+        distance_model="fai_sphere",
+    )
+    assert tuple(results[0]) == tuple(windowed)
+    assert tuple(results[1]) == tuple(engine)
+    assert results[1][0][0] >= 2000
+    with pytest.raises(ValueError):
+        score_rs.optimize_many([(lon, lat, alt, time)], 2, start_time=[release, None])
+
+
+def test_result_and_altitude_rule():
+    release = seconds_since_midnight(dt.time(8, 12, 29))
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc", altitude="pressure")
+    lon, lat, alt, time = flight.longitude, flight.latitude, flight.altitude, flight.time
+    res = score_rs.optimize(lon, lat, alt, 6, time=time, start_time=release)
+    assert res.status == "complete"
+    assert res.path == [131, 1067, 2978, 4010, 5179, 7181, 8259]
+    assert_almost_equal(res.distance, 1018.54, 2)
+    assert len(res.legs) == 6
+    assert res.upper_bound >= res.distance - 1.0
+    assert res.dropped >= 0
+
+    relative = score_rs.optimize(
+        lon, lat, alt, 6, time=time, start_time=release, altitude_rule="0.02%"
+    )
+    assert relative.path == [152, 1067, 2978, 4010, 5179, 7181, 8254]
+    assert_almost_equal(relative.distance, 1015.53, 1)
+
+    with pytest.raises(ValueError):
+        score_rs.optimize(lon, lat, alt, 6, altitude_rule="1000 m")
+
+    # no path is longer than break_at, so the optimization stops early
+    early = score_rs.optimize(lon, lat, alt, 6, time=time, start_time=release, break_at=2000.0)
+    assert early.distance <= res.distance
+
+
+def test_altitudes():
+    flight = score_rs.read_igc("fixtures/2023-06-17_288167.igc")
+    lon, lat, alt = flight.longitude, flight.latitude, flight.altitude
+    path, distance = score_rs.optimize(lon, lat, alt, 2)
+    float_path, float_distance = score_rs.optimize(lon, lat, alt.astype(float) + 0.2, 2)
+    assert (float_path, float_distance) == (path, distance)
 
-    res = score_rs.optimize(data[0], data[1], data[2], 2)
-    assert_almost_equal(res[1], 804.95, 2)
-    assert res[0] == [887, 3886, 7801]
+    wrapping = alt.copy()
+    wrapping[10] = 70_000
+    with pytest.raises(ValueError, match="index 10"):
+        score_rs.optimize(lon, lat, wrapping, 2)
+    garbage = alt.astype(float)
+    garbage[20] = np.nan
+    with pytest.raises(ValueError, match="index 20"):
+        score_rs.optimize(lon, lat, garbage, 2)
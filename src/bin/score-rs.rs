@@ -0,0 +1,539 @@
+// Score flights from IGC or GPX files on the command line and print the results as a table or JSON.
+
+use std::env;
+use std::fmt::Write;
+use std::process::ExitCode;
+
+use score_rs::distance::Vincenty;
+use score_rs::flight::{AltitudePolicy, Fix, Flight};
+use score_rs::float::Float;
+use score_rs::phases::Phases;
+use score_rs::point::{AltitudeRule, Constraints, EngineRule};
+use score_rs::result::Leg;
+use score_rs::{free, gpx, out_and_return, triangle};
+
+const USAGE: &str = "Usage: score-rs [OPTIONS] FILE...
+
+Score flights from IGC or GPX files.
+
+Options:
+  -l, --legs N              number of legs of the free distance [default: 6]
+  -a, --altitude RULE       maximum altitude loss: meters (1000), percent of the distance (1%)
+                            or none [default: 1000]
+  -d, --discipline NAME     free, fai, flat or out-and-return [default: free]
+  -r, --release TIME        score only fixes after the release: a time (UTC) like 08:12:29
+                            or auto to detect the release and the landing
+  -c, --closing KM          maximum distance between start and finish of closed courses [default: 1]
+  -e, --engine RULE         fixes with an engine noise level (ENL or MOP) above 500: ignore, after-last-run
+                            to score only the fixes after the last engine run or no-turnpoints (free only)
+                            to place no turnpoint on them [default: ignore]
+  -m, --memory-budget MB    calculate the distances on the fly if the distance matrix of the free distance
+                            needs more megabytes, which is slower but needs less memory (free only)
+      --exact               calculate in double precision, which is slower but avoids errors of a few meters
+      --json                print the results as JSON
+  -h, --help                print this help";
+
+// The engine noise level above which the engine is running
+const ENGINE_THRESHOLD: u16 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Discipline {
+    Free,
+    Fai,
+    Flat,
+    OutAndReturn,
+}
+
+impl Discipline {
+    fn name(&self) -> &'static str {
+        match self {
+            Discipline::Free => "free",
+            Discipline::Fai => "fai",
+            Discipline::Flat => "flat",
+            Discipline::OutAndReturn => "out-and-return",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Release {
+    None,
+    Auto,
+    // Seconds since midnight (UTC)
+    Time(i64),
+}
+
+#[derive(Debug)]
+struct Options {
+    legs: usize,
+    altitude: AltitudeRule,
+    discipline: Discipline,
+    release: Release,
+    closing_distance: f64,
+    engine: EngineRule,
+    // The maximum size of the distance matrix in bytes
+    memory_budget: Option<usize>,
+    exact: bool,
+    json: bool,
+    files: Vec<String>,
+}
+
+// The result of a flight with the indices of the fixes in the file
+struct Score {
+    path: Vec<usize>,
+    distance: f64,
+    legs: Vec<Leg<f64>>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let results: Vec<(&str, Result<Score, String>)> = options
+        .files
+        .iter()
+        .map(|file| (file.as_str(), score(file, &options)))
+        .collect();
+    if options.json {
+        println!("{}", to_json(&results, options.discipline));
+    } else {
+        for (file, result) in &results {
+            match result {
+                Ok(score) => print!("{}", to_table(file, score, options.discipline)),
+                Err(err) => eprintln!("{}: {}", file, err),
+            }
+        }
+    }
+    if results.iter().all(|(_, result)| result.is_ok()) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// Return None if the help was requested
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        legs: 6,
+        altitude: AltitudeRule::default(),
+        discipline: Discipline::Free,
+        release: Release::None,
+        closing_distance: 1.0,
+        engine: EngineRule::Ignore,
+        memory_budget: None,
+        exact: false,
+        json: false,
+        files: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-l" | "--legs" => {
+                options.legs = match value()?.parse() {
+                    Ok(legs) if legs > 0 => legs,
+                    _ => return Err("the number of legs must be a positive integer".to_string()),
+                }
+            }
+            "-a" | "--altitude" => options.altitude = parse_altitude(&value()?)?,
+            "-d" | "--discipline" => {
+                options.discipline = match value()?.as_str() {
+                    "free" => Discipline::Free,
+                    "fai" => Discipline::Fai,
+                    "flat" => Discipline::Flat,
+                    "out-and-return" => Discipline::OutAndReturn,
+                    other => return Err(format!("unknown discipline {}", other)),
+                }
+            }
+            "-r" | "--release" => {
+                options.release = match value()?.as_str() {
+                    "auto" => Release::Auto,
+                    time => Release::Time(
+                        parse_time(time).ok_or(format!("invalid release time {}", time))?,
+                    ),
+                }
+            }
+            "-c" | "--closing" => {
+                options.closing_distance = match value()?.parse() {
+                    Ok(distance) if distance >= 0.0 => distance,
+                    _ => {
+                        return Err("the closing distance must be a non-negative number".to_string())
+                    }
+                }
+            }
+            "-e" | "--engine" => {
+                options.engine = match value()?.as_str() {
+                    "ignore" => EngineRule::Ignore,
+                    "after-last-run" => EngineRule::AfterLastRun {
+                        threshold: ENGINE_THRESHOLD,
+                    },
+                    "no-turnpoints" => EngineRule::NoTurnpoints {
+                        threshold: ENGINE_THRESHOLD,
+                    },
+                    other => return Err(format!("unknown engine rule {}", other)),
+                }
+            }
+            "-m" | "--memory-budget" => {
+                options.memory_budget = match value()?.parse::<usize>() {
+                    Ok(megabytes) => Some(megabytes.saturating_mul(1 << 20)),
+                    _ => return Err("the memory budget must be a number of megabytes".to_string()),
+                }
+            }
+            "--exact" => options.exact = true,
+            "--json" => options.json = true,
+            option if option.starts_with('-') => return Err(format!("unknown option {}", option)),
+            _ => options.files.push(arg),
+        }
+    }
+    if options.files.is_empty() {
+        return Err("no files given".to_string());
+    }
+    if matches!(options.engine, EngineRule::NoTurnpoints { .. })
+        && options.discipline != Discipline::Free
+    {
+        return Err("no-turnpoints is only supported by the free distance".to_string());
+    }
+    if options.memory_budget.is_some() && options.discipline != Discipline::Free {
+        return Err("the memory budget is only supported by the free distance".to_string());
+    }
+    Ok(Some(options))
+}
+
+fn parse_altitude(rule: &str) -> Result<AltitudeRule, String> {
+    let invalid = || format!("invalid altitude rule {}", rule);
+    if rule == "none" {
+        Ok(AltitudeRule::Disabled)
+    } else if let Some(percent) = rule.strip_suffix('%') {
+        let percent: f64 = percent.parse().map_err(|_| invalid())?;
+        Ok(AltitudeRule::Relative(percent / 100.0))
+    } else {
+        rule.parse().map(AltitudeRule::Fixed).map_err(|_| invalid())
+    }
+}
+
+// Parse HH:MM or HH:MM:SS into seconds since midnight
+fn parse_time(time: &str) -> Option<i64> {
+    let parts: Vec<i64> = time
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    match parts[..] {
+        [hours, minutes] if hours < 24 && minutes < 60 => Some((hours * 60 + minutes) * 60),
+        [hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 => {
+            Some((hours * 60 + minutes) * 60 + seconds)
+        }
+        _ => None,
+    }
+}
+
+fn score(file: &str, options: &Options) -> Result<Score, String> {
+    let flight = if file.to_lowercase().ends_with(".gpx") {
+        gpx::read(file).map_err(|err| err.to_string())?
+    } else {
+        Flight::read(file, AltitudePolicy::Auto).map_err(|err| err.to_string())?
+    };
+    let fixes = &flight.fixes;
+
+    // the time window and the engine rule of the fixes which are scored
+    let constraints = Constraints {
+        altitude: options.altitude,
+        engine: options.engine,
+        ..Default::default()
+    };
+    let constraints = match options.release {
+        Release::None => constraints,
+        Release::Time(time) => Constraints {
+            start_time: Some(time),
+            ..constraints
+        },
+        Release::Auto => Phases::detect(fixes)
+            .map_err(|err| err.to_string())?
+            .with_scoring_window(fixes, constraints),
+    };
+
+    if options.exact {
+        optimize(fixes, options, constraints, options.closing_distance)
+    } else {
+        optimize(fixes, options, constraints, options.closing_distance as f32)
+    }
+}
+
+// Optimize the fixes in the precision of F. The free distance applies the constraints itself, closed
+// courses are optimized on the scoring window of the constraints. The indices of the score refer to all fixes.
+fn optimize<F: Float>(
+    fixes: &[Fix],
+    options: &Options,
+    constraints: Constraints,
+    closing_distance: F,
+) -> Result<Score, String> {
+    let (path, distance, legs) = match options.discipline {
+        Discipline::Free => {
+            let budget = free::Budget {
+                memory: options.memory_budget,
+                ..Default::default()
+            };
+            let result = free::optimize_within(
+                fixes,
+                F::zero(),
+                options.legs,
+                constraints,
+                &Vincenty,
+                budget,
+            )
+            .map_err(|err| err.to_string())?
+            .result;
+            (result.path, result.distance, result.legs)
+        }
+        discipline => {
+            let window = constraints
+                .scoring_window(fixes)
+                .map_err(|err| err.to_string())?;
+            let route = &fixes[window.clone()];
+            let result = match discipline {
+                Discipline::Fai => {
+                    triangle::optimize(route, closing_distance, options.altitude, &Vincenty)
+                }
+                Discipline::Flat => {
+                    triangle::optimize_flat(route, closing_distance, options.altitude, &Vincenty)
+                }
+                _ => out_and_return::optimize(route, closing_distance, options.altitude, &Vincenty),
+            }
+            .map_err(|err| err.to_string())?;
+            let path = result
+                .path
+                .iter()
+                .map(|index| index + window.start)
+                .collect();
+            let legs = result
+                .legs
+                .into_iter()
+                .map(|leg| Leg {
+                    start: leg.start + window.start,
+                    stop: leg.stop + window.start,
+                    ..leg
+                })
+                .collect();
+            (path, result.distance, legs)
+        }
+    };
+    let to_f64 = |value: F| value.to_f64().unwrap();
+    Ok(Score {
+        path,
+        distance: to_f64(distance),
+        legs: legs
+            .into_iter()
+            .map(|leg| Leg {
+                start: leg.start,
+                stop: leg.stop,
+                distance: to_f64(leg.distance),
+                bearing: to_f64(leg.bearing),
+                start_altitude: leg.start_altitude,
+                stop_altitude: leg.stop_altitude,
+            })
+            .collect(),
+    })
+}
+
+fn to_table(file: &str, score: &Score, discipline: Discipline) -> String {
+    let mut table = format!("{}: {} {:.2} km\n", file, discipline.name(), score.distance);
+    writeln!(
+        table,
+        "{:>5} {:>8} {:>8} {:>10} {:>8} {:>8} {:>8}",
+        "leg", "start", "stop", "km", "bearing", "alt", "alt"
+    )
+    .unwrap();
+    for (number, leg) in score.legs.iter().enumerate() {
+        writeln!(
+            table,
+            "{:>5} {:>8} {:>8} {:>10.2} {:>8.1} {:>8} {:>8}",
+            number + 1,
+            leg.start,
+            leg.stop,
+            leg.distance,
+            leg.bearing,
+            leg.start_altitude,
+            leg.stop_altitude
+        )
+        .unwrap();
+    }
+    table
+}
+
+fn to_json(results: &[(&str, Result<Score, String>)], discipline: Discipline) -> String {
+    let flights: Vec<String> = results
+        .iter()
+        .map(|(file, result)| match result {
+            Ok(score) => {
+                let path: Vec<String> = score.path.iter().map(|index| index.to_string()).collect();
+                let legs: Vec<String> = score
+                    .legs
+                    .iter()
+                    .map(|leg| {
+                        format!(
+                            r#"{{"start":{},"stop":{},"distance":{},"bearing":{},"start_altitude":{},"stop_altitude":{}}}"#,
+                            leg.start,
+                            leg.stop,
+                            leg.distance,
+                            leg.bearing,
+                            leg.start_altitude,
+                            leg.stop_altitude
+                        )
+                    })
+                    .collect();
+                format!(
+                    r#"{{"file":{},"discipline":"{}","distance":{},"path":[{}],"legs":[{}]}}"#,
+                    json_string(file),
+                    discipline.name(),
+                    score.distance,
+                    path.join(","),
+                    legs.join(",")
+                )
+            }
+            Err(err) => format!(
+                r#"{{"file":{},"error":{}}}"#,
+                json_string(file),
+                json_string(err)
+            ),
+        })
+        .collect();
+    format!("[{}]", flights.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> Result<Option<Options>, String> {
+        parse_args(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = args(
+            "-l 3 -a 2% -d fai -r 08:12:29 -c 2.5 -e after-last-run --exact --json a.igc b.gpx",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(options.memory_budget, None);
+        assert_eq!(options.legs, 3);
+        assert_eq!(options.altitude, AltitudeRule::Relative(0.02));
+        assert_eq!(options.discipline, Discipline::Fai);
+        assert_eq!(options.release, Release::Time(8 * 3600 + 12 * 60 + 29));
+        assert_eq!(options.closing_distance, 2.5);
+        assert_eq!(options.engine, EngineRule::AfterLastRun { threshold: 500 });
+        assert!(options.exact);
+        assert!(options.json);
+        assert_eq!(options.files, ["a.igc", "b.gpx"]);
+
+        let options = args("a.igc").unwrap().unwrap();
+        assert_eq!(options.altitude, AltitudeRule::Fixed(1000));
+        assert_eq!(options.discipline, Discipline::Free);
+        assert_eq!(options.release, Release::None);
+        assert!(!options.exact);
+        let options = args("-m 512 a.igc").unwrap().unwrap();
+        assert_eq!(options.memory_budget, Some(512 << 20));
+        assert!(args("--help").unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_options_are_errors() {
+        for invalid in [
+            "",
+            "-l 0 a.igc",
+            "-a high a.igc",
+            "-d zigzag a.igc",
+            "-r 25:00 a.igc",
+            "--bogus a.igc",
+            "-e sometimes a.igc",
+            "-d fai -e no-turnpoints a.igc",
+            "-m lots a.igc",
+            "-d fai -m 512 a.igc",
+            "a.igc -l",
+        ] {
+            assert!(args(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(parse_altitude("none"), Ok(AltitudeRule::Disabled));
+        assert_eq!(parse_time("08:12"), Some(8 * 3600 + 12 * 60));
+    }
+
+    const FIXTURE: &str = "fixtures/2023-06-17_288167.igc";
+
+    fn score_fixture(args: &str) -> Score {
+        let options = parse_args(
+            args.split_whitespace()
+                .map(str::to_string)
+                .chain([FIXTURE.to_string()]),
+        )
+        .unwrap()
+        .unwrap();
+        score(FIXTURE, &options).unwrap()
+    }
+
+    #[test]
+    fn score_prints_table_and_json() {
+        let score = score_fixture("-l 2 -r auto");
+        assert_eq!(score.path, [1019, 4017, 7923]);
+        assert_eq!(score.legs.len(), 2);
+        assert!((score.distance - 804.38).abs() < 0.01);
+
+        let table = to_table(FIXTURE, &score, Discipline::Free);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], format!("{}: free 804.38 km", FIXTURE));
+        assert_eq!(lines.len(), 4);
+        let first: Vec<&str> = lines[2].split_whitespace().collect();
+        assert_eq!(first[..3], ["1", "1019", "4017"]);
+
+        let missing = Err("the file can not be read".to_string());
+        let json = to_json(
+            &[(FIXTURE, Ok(score)), ("missing.igc", missing)],
+            Discipline::Free,
+        );
+        assert!(json.starts_with(&format!(
+            r#"[{{"file":"{}","discipline":"free","distance":804.3"#,
+            FIXTURE
+        )));
+        assert!(json.contains(r#""path":[1019,4017,7923],"legs":[{"start":1019,"stop":4017,"#));
+        assert!(json.ends_with(r#"{"file":"missing.igc","error":"the file can not be read"}]"#));
+    }
+
+    #[test]
+    fn memory_budget_gives_the_same_score() {
+        let score = score_fixture("-l 2 -r auto -m 0");
+        assert_eq!(score.path, [1019, 4017, 7923]);
+        assert!((score.distance - 804.38).abs() < 0.01);
+    }
+
+    #[test]
+    fn closed_course_indices_refer_to_the_whole_file() {
+        let release = score_fixture("-d out-and-return -c 5 -r auto");
+        assert_eq!(release.path, [1066, 4039]);
+        assert_eq!((release.legs[0].start, release.legs[1].stop), (1066, 1066));
+        // the release time keeps the fixes before it out of the result
+        let late = score_fixture("-d out-and-return -c 5 -r 12:00");
+        assert!(late.path[0] > release.path[0]);
+        assert!(late.distance < release.distance);
+    }
+}
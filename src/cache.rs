@@ -3,26 +3,28 @@
 // cache which is sufficiently similiar to the current candidate can be used to place an upper bound
 // on the maximum achievable distance with this candidate.
 
+use std::collections::BTreeMap;
+
+use crate::float::Float;
 use crate::graph::StartCandidate;
 use crate::point::ApproxDistance;
 use flat_projection::FlatPoint;
-use std::collections::HashSet;
 
-pub struct CacheItem {
+pub struct CacheItem<F> {
     pub start: usize,
-    pub stops: HashSet<usize>,
+    pub stops: StopSet,
     pub max_stop: usize,
-    pub distance: f32,
+    pub distance: F,
 }
 
-impl CacheItem {
-    pub fn from_candidate(candidate: &StartCandidate, stops: HashSet<usize>) -> CacheItem {
-        let max_stop = *stops.iter().max().unwrap();
+impl<F: Float> CacheItem<F> {
+    pub fn from_candidate(candidate: &StartCandidate<F>, stops: StopSet) -> Self {
+        let max_stop = stops.max().unwrap();
         CacheItem {
             start: candidate.start,
             stops,
             max_stop,
-            distance: 0.0,
+            distance: F::zero(),
         }
     }
     // If the current stop set of an incoming item is super set of the stop set of the cached item,
@@ -40,9 +42,9 @@ impl CacheItem {
     // can not be used to calculate an upper bound.
     pub fn places_upperbound(
         &self,
-        candidate: &mut CacheItem,
-        flat_points: &[FlatPoint<f32>],
-        best_distance: f32,
+        candidate: &mut CacheItem<F>,
+        flat_points: &[FlatPoint<F>],
+        best_distance: F,
     ) -> bool {
         let start_offset = flat_points.distance(self.start, candidate.start);
         candidate.distance = self.distance + start_offset;
@@ -54,7 +56,7 @@ impl CacheItem {
             return false;
         }
         for to_check in candidate.stops.difference(&self.stops) {
-            let stop_offset = flat_points.distance(self.max_stop, *to_check);
+            let stop_offset = flat_points.distance(self.max_stop, to_check);
             let new_guess = stop_offset + start_offset + self.distance;
             candidate.distance = candidate.distance.max(new_guess);
             if candidate.distance > best_distance {
@@ -65,32 +67,168 @@ impl CacheItem {
     }
 }
 
-pub struct Cache {
-    items: Vec<CacheItem>,
+// A set of stop indices, stored as a bitset from the word of the first stop to the word of the last stop.
+// The stops of a candidate are the fixes after its start which comply with the constraints, so they
+// mostly form long runs and the set operations of the cache work on 64 stops at once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StopSet {
+    // the index of the first word
+    offset: usize,
+    words: Vec<u64>,
+    // the number of stops
+    len: usize,
+}
+
+impl StopSet {
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn min(&self) -> Option<usize> {
+        self.words
+            .first()
+            .map(|word| self.offset * 64 + word.trailing_zeros() as usize)
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        self.words.last().map(|word| {
+            (self.offset + self.words.len() - 1) * 64 + 63 - word.leading_zeros() as usize
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.difference(&EMPTY)
+    }
+
+    pub fn is_superset(&self, other: &StopSet) -> bool {
+        other
+            .words
+            .iter()
+            .enumerate()
+            .all(|(index, &word)| word & !self.word(other.offset + index) == 0)
+    }
+
+    // The stops which are not in other, in increasing order
+    pub fn difference<'a>(&'a self, other: &'a StopSet) -> impl Iterator<Item = usize> + 'a {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, &word)| {
+                let index = self.offset + index;
+                bits(word & !other.word(index)).map(move |bit| index * 64 + bit)
+            })
+    }
+
+    fn word(&self, index: usize) -> u64 {
+        index
+            .checked_sub(self.offset)
+            .and_then(|index| self.words.get(index))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+static EMPTY: StopSet = StopSet {
+    offset: 0,
+    words: Vec::new(),
+    len: 0,
+};
+
+impl FromIterator<usize> for StopSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(stops: I) -> Self {
+        let stops: Vec<usize> = stops.into_iter().collect();
+        let (Some(&min), Some(&max)) = (stops.iter().min(), stops.iter().max()) else {
+            return StopSet::default();
+        };
+        let offset = min / 64;
+        let mut words = vec![0_u64; max / 64 - offset + 1];
+        for stop in stops {
+            words[stop / 64 - offset] |= 1 << (stop % 64);
+        }
+        let len = words.iter().map(|word| word.count_ones() as usize).sum();
+        StopSet { offset, words, len }
+    }
+}
+
+// The positions of the set bits of a word, in increasing order
+fn bits(mut word: u64) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        (word != 0).then(|| {
+            let bit = word.trailing_zeros() as usize;
+            word &= word - 1;
+            bit
+        })
+    })
+}
+
+// How often the cache ruled out a candidate (hits) or not (misses),
+// and how many items it compared with the candidates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub scanned: usize,
+}
+
+#[derive(Default)]
+pub struct Cache<F> {
+    // the items by their first stop, each in the order they were set
+    items: BTreeMap<usize, Vec<CacheItem<F>>>,
+    stats: CacheStats,
 }
 
 // Save start candidates and their valid end (stop) points. It is used to quickly determine (based on the stop sets and max distances of previous
 // candidates) if a candidate can lead to a better result than the current best distances
-impl Cache {
-    pub fn new() -> Cache {
-        Cache { items: Vec::new() }
+impl<F: Float> Cache<F> {
+    pub fn new() -> Self {
+        Cache {
+            items: BTreeMap::new(),
+            stats: CacheStats::default(),
+        }
     }
-    pub fn set(&mut self, item: CacheItem) {
-        self.items.push(item);
+    pub fn set(&mut self, item: CacheItem<F>) {
+        let first = item.stops.min().unwrap_or(item.max_stop);
+        self.items.entry(first).or_default().push(item);
     }
 
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // An item can only be a sub set of the stops of the candidate if its first stop lies between
+    // the first and the last stop of the candidate, so only the items with these keys are visited.
+    // Of them, only items with a distance below best_distance and at most as many stops as the
+    // candidate are compared. The items are visited from the last first stop and the last item
+    // set, see benches/cache.rs for a measurement.
     pub fn check(
         &mut self,
-        candidate: &mut CacheItem,
-        flat_points: &[FlatPoint<f32>],
-        best_distance: f32,
+        candidate: &mut CacheItem<F>,
+        flat_points: &[FlatPoint<F>],
+        best_distance: F,
     ) -> bool {
-        // iterate in reverse order as it provides a speed-up on a broad test suite of files
-        for cache_item in self.items.iter().rev() {
-            if cache_item.places_upperbound(candidate, flat_points, best_distance) {
-                return true;
+        let (Some(first), Some(last)) = (candidate.stops.min(), candidate.stops.max()) else {
+            self.stats.misses += 1;
+            return false;
+        };
+        let len = candidate.stops.len();
+        for items in self.items.range(first..=last).rev().map(|(_, items)| items) {
+            for item in items.iter().rev() {
+                if item.distance >= best_distance || item.stops.len() > len || item.max_stop > last
+                {
+                    continue;
+                }
+                self.stats.scanned += 1;
+                if item.places_upperbound(candidate, flat_points, best_distance) {
+                    self.stats.hits += 1;
+                    return true;
+                }
             }
         }
+        self.stats.misses += 1;
         false
     }
 }
@@ -172,18 +310,24 @@ mod tests {
         let first_item = CacheItem {
             start: 0,
             max_stop: 1,
-            stops: HashSet::new(),
+            stops: StopSet::default(),
             distance: 0.0,
         };
         let second_item = CacheItem {
             start: 1,
             max_stop: 2,
-            stops: HashSet::new(),
+            stops: StopSet::default(),
             distance: 0.0,
         };
         cache.set(first_item);
         cache.set(second_item);
-        assert_eq!(cache.items.get(1).map(|item| item.start), Some(1));
+        let starts: Vec<usize> = cache
+            .items
+            .values()
+            .flatten()
+            .map(|item| item.start)
+            .collect();
+        assert_eq!(starts, [0, 1]);
     }
 
     #[test]
@@ -191,7 +335,7 @@ mod tests {
         let flat_points = vec![FlatPoint { x: 0.0, y: 0.0 }, FlatPoint { x: 1.0, y: 1.0 }];
         let mut candidate = CacheItem {
             start: 0,
-            stops: HashSet::new(),
+            stops: StopSet::default(),
             max_stop: 0,
             distance: 0.0,
         };
@@ -222,5 +366,139 @@ mod tests {
         // set a high best distance to make sure the cache item stays below
         let best_distance = 1_000.0;
         assert!(cache.check(&mut candidate, &flat_points, best_distance));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 0,
+                scanned: 1
+            }
+        );
+    }
+
+    #[test]
+    fn check_skips_items_with_more_stops_or_above_best_distance() {
+        let flat_points: Vec<FlatPoint<f32>> = (0..3)
+            .map(|i| FlatPoint {
+                x: i as f32,
+                y: i as f32,
+            })
+            .collect();
+        let mut cache = Cache::new();
+        for (stops, distance) in [(vec![1], 50.0), (vec![0, 1, 2], 5.0), (vec![1], 500.0)] {
+            cache.set(CacheItem {
+                start: 1,
+                max_stop: 1,
+                stops: stops.into_iter().collect(),
+                distance,
+            });
+        }
+        let mut candidate = CacheItem {
+            start: 0,
+            stops: [0, 1].into_iter().collect(),
+            max_stop: 1,
+            distance: 0.0,
+        };
+        // the item with three stops can not be a sub set of the candidate and the item with 500 km
+        // is above the best distance, so only the first item is compared
+        assert!(!cache.check(&mut candidate, &flat_points, 51.0));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 1,
+                scanned: 1
+            }
+        );
+    }
+
+    #[test]
+    fn check_compares_the_last_items_first() {
+        let flat_points: Vec<FlatPoint<f32>> = (0..4)
+            .map(|i| FlatPoint {
+                x: i as f32,
+                y: 0.0,
+            })
+            .collect();
+        let mut cache = Cache::new();
+        for (start, stops, distance) in [
+            (1, vec![3], 30.0),
+            (2, vec![2, 3], 10.0),
+            (1, vec![3], 20.0),
+        ] {
+            cache.set(CacheItem {
+                start,
+                max_stop: 3,
+                stops: stops.into_iter().collect(),
+                distance,
+            });
+        }
+        let mut candidate = CacheItem {
+            start: 0,
+            stops: [1, 2, 3].into_iter().collect(),
+            max_stop: 3,
+            distance: 0.0,
+        };
+        // all items place an upper bound, the one set last is found first
+        assert!(cache.check(&mut candidate, &flat_points, 100.0));
+        assert_eq!(cache.stats().scanned, 1);
+        assert_eq!(candidate.distance, 23.0);
+    }
+
+    #[test]
+    fn check_only_visits_items_within_the_stops_of_the_candidate() {
+        let flat_points: Vec<FlatPoint<f32>> = (0..6)
+            .map(|i| FlatPoint {
+                x: i as f32,
+                y: 0.0,
+            })
+            .collect();
+        let mut cache = Cache::new();
+        for stops in [vec![0, 1], vec![4, 5], vec![2, 5]] {
+            cache.set(CacheItem {
+                start: 1,
+                max_stop: *stops.last().unwrap(),
+                stops: stops.into_iter().collect(),
+                distance: 5.0,
+            });
+        }
+        let mut candidate = CacheItem {
+            start: 0,
+            stops: [2, 3, 4].into_iter().collect(),
+            max_stop: 4,
+            distance: 0.0,
+        };
+        // the first item starts before the stops of the candidate, the others end after them
+        assert!(!cache.check(&mut candidate, &flat_points, 100.0));
+        assert_eq!(cache.stats().scanned, 0);
+        let mut candidate = CacheItem {
+            start: 0,
+            stops: [2, 3, 4, 5].into_iter().collect(),
+            max_stop: 5,
+            distance: 0.0,
+        };
+        assert!(cache.check(&mut candidate, &flat_points, 100.0));
+        assert_eq!(cache.stats().scanned, 1);
+    }
+
+    #[test]
+    fn stop_set_operations() {
+        let stops: StopSet = (60..200).filter(|stop| stop % 7 != 0).collect();
+        let subset: StopSet = (130..150).filter(|stop| stop % 7 != 0).collect();
+        assert_eq!(stops.len(), 140 - 20);
+        assert_eq!((stops.min(), stops.max()), (Some(60), Some(199)));
+        assert_eq!(stops.iter().take(3).collect::<Vec<_>>(), [60, 61, 62]);
+        assert!(stops.is_superset(&subset));
+        assert!(!subset.is_superset(&stops));
+        assert!(stops.is_superset(&StopSet::default()));
+        assert!(StopSet::default().is_empty());
+        assert_eq!(
+            stops.difference(&subset).collect::<Vec<_>>(),
+            stops
+                .iter()
+                .filter(|stop| !(130..150).contains(stop))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(stops.iter().count(), 140 - 20);
     }
 }
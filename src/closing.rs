@@ -0,0 +1,171 @@
+// Closed courses (triangles, out-and-return) require that the track returns to its starting area:
+// there has to be a start point before the first turnpoint and a finish point after the last
+// turnpoint which are not further apart than the closing distance. The start and finish have to
+// comply with the altitude rule, by default the finish must not be more than 1000m below the start.
+
+use flat_projection::FlatPoint;
+
+use crate::float::Float;
+use crate::parallel::*;
+use crate::point::{AltitudeRule, Point, Valid};
+
+// For every point a, store the start (at or before a) and the stop that can be reached
+// farthest into the track while still closing the route.
+pub struct Closing {
+    pub reach: Vec<(usize, usize)>,
+}
+
+impl Closing {
+    // The altitude rule is checked for a route with the given distance in km
+    pub fn new<F: Float, T: Point>(
+        flat_points: &[FlatPoint<F>],
+        route: &[T],
+        closing_distance: F,
+        altitude: AltitudeRule,
+        distance: F,
+    ) -> Self {
+        // the last point which is close enough to close the route for each start,
+        // the start itself always qualifies. The distances are calculated on the fly,
+        // which avoids the memory of a distance matrix for long tracks.
+        let last_stops: Vec<usize> = opt_par_iter(flat_points)
+            .enumerate()
+            .map(|(start, point)| {
+                (start + 1..flat_points.len())
+                    .rev()
+                    .find(|&stop| {
+                        point.distance(&flat_points[stop]) <= closing_distance
+                            && route.valid(start, stop, altitude.into(), distance)
+                    })
+                    .unwrap_or(start)
+            })
+            .collect();
+
+        let mut reach = Vec::with_capacity(last_stops.len());
+        let mut best = (0, 0);
+        for (start, &stop) in last_stops.iter().enumerate() {
+            if start == 0 || stop > best.1 {
+                best = (start, stop);
+            }
+            reach.push(best);
+        }
+        Closing { reach }
+    }
+
+    // Return if there is a closed route with a turnpoint at first and one at last
+    pub fn closes(&self, first: usize, last: usize) -> bool {
+        self.reach[first].1 >= last
+    }
+}
+
+// Find the best closed course with solve, which returns the course and its distance for a closing.
+// A relative altitude rule depends on the distance of the course, which is unknown when the closing
+// is calculated. Starting without a limit on the distance, the closing is recalculated with the
+// distance of the best course until it does not get shorter anymore. The allowed altitude loss never
+// decreases with the distance, so the last course is valid and no valid course is longer.
+pub fn find_best_closed<F: Float, T: Point, C>(
+    flat_points: &[FlatPoint<F>],
+    route: &[T],
+    closing_distance: F,
+    altitude: AltitudeRule,
+    mut solve: impl FnMut(&Closing) -> Option<(C, F)>,
+) -> Option<(C, Closing)> {
+    let mut distance = F::max_value();
+    loop {
+        let closing = Closing::new(flat_points, route, closing_distance, altitude, distance);
+        let (course, course_distance) = solve(&closing)?;
+        if !matches!(altitude, AltitudeRule::Relative(_)) || course_distance >= distance {
+            return Some((course, closing));
+        }
+        distance = course_distance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PointImpl;
+
+    fn route(altitudes: &[i16]) -> Vec<PointImpl> {
+        altitudes
+            .iter()
+            .map(|&altitude| PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude,
+                timestamp: None,
+                engine_noise: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn closing_reaches_last_close_point() {
+        let flat_points = [
+            FlatPoint { x: 0.0, y: 0.0 },
+            FlatPoint { x: 10.0, y: 0.0 },
+            FlatPoint { x: 0.5, y: 0.0 },
+            FlatPoint { x: 20.0, y: 0.0 },
+        ];
+        let route = route(&[0, 0, 0, 0]);
+        let closing = Closing::new(&flat_points, &route, 1.0, AltitudeRule::default(), 0.0);
+        assert_eq!(closing.reach, vec![(0, 2), (0, 2), (0, 2), (3, 3)]);
+        assert!(closing.closes(1, 2));
+        assert!(!closing.closes(1, 3));
+    }
+
+    #[test]
+    fn closing_respects_altitude_rule() {
+        let flat_points = [
+            FlatPoint { x: 0.0, y: 0.0 },
+            FlatPoint { x: 10.0, y: 0.0 },
+            FlatPoint { x: 0.5, y: 0.0 },
+        ];
+        let route = route(&[1500, 1000, 0]);
+        let closing =
+            |altitude, distance| Closing::new(&flat_points, &route, 1.0, altitude, distance).reach;
+        assert_eq!(
+            closing(AltitudeRule::default(), 0.0),
+            vec![(0, 0), (1, 1), (2, 2)]
+        );
+        assert_eq!(
+            closing(AltitudeRule::Fixed(1500), 0.0),
+            vec![(0, 2), (0, 2), (0, 2)]
+        );
+        assert_eq!(
+            closing(AltitudeRule::Relative(0.01), 100.0),
+            vec![(0, 0), (1, 1), (2, 2)]
+        );
+        assert_eq!(
+            closing(AltitudeRule::Relative(0.01), 150.0),
+            vec![(0, 2), (0, 2), (0, 2)]
+        );
+    }
+
+    #[test]
+    fn closed_course_complies_with_relative_rule() {
+        // the course to point 1 can only be closed at point 2 which is 1500m lower,
+        // which is only allowed for courses of at least 150 km
+        let flat_points = [
+            FlatPoint { x: 0.0, y: 0.0 },
+            FlatPoint { x: 60.0, y: 0.0 },
+            FlatPoint { x: 0.5, y: 0.0 },
+        ];
+        let route = route(&[1500, 1500, 0]);
+        let course = |closing: &Closing| {
+            let distance = 2.0 * flat_points[0].distance(&flat_points[1]);
+            closing.closes(0, 1).then_some((1, distance))
+        };
+        let best = |ratio| {
+            find_best_closed(
+                &flat_points,
+                &route,
+                1.0,
+                AltitudeRule::Relative(ratio),
+                course,
+            )
+            .map(|(course, _)| course)
+        };
+        assert_eq!(best(0.01), None);
+        assert_eq!(best(0.02), Some(1));
+    }
+}
@@ -0,0 +1,142 @@
+use flat_projection::FlatProjection;
+
+use crate::flat::projection_center;
+use crate::float::Float;
+use crate::karney::karney_inverse;
+use crate::point::Point;
+use crate::vincenty::vincenty_inverse;
+
+// The radius of the FAI sphere in km, which the FAI sporting code prescribes for records and badges
+pub const FAI_EARTH_RADIUS: f64 = 6371.;
+
+// The geodesic model used for the final distances of a result. The optimization itself always
+// works on the flat projection, the model only measures the legs of the best path.
+pub trait DistanceModel: Sync {
+    // Return the distance in km and the initial bearing in degrees [0, 360) from fix1 to fix2,
+    // or None if the calculation does not converge
+    fn inverse<F: Float, T: Point>(&self, fix1: &T, fix2: &T) -> Option<(F, F)>;
+}
+
+// Vincenty's iterative solution on the WGS-84 ellipsoid, which fails to converge for nearly
+// antipodal points
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Vincenty;
+
+impl DistanceModel for Vincenty {
+    fn inverse<F: Float, T: Point>(&self, fix1: &T, fix2: &T) -> Option<(F, F)> {
+        vincenty_inverse(fix1, fix2)
+    }
+}
+
+// Karney's solution on the WGS-84 ellipsoid, which always converges
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Karney;
+
+impl DistanceModel for Karney {
+    fn inverse<F: Float, T: Point>(&self, fix1: &T, fix2: &T) -> Option<(F, F)> {
+        Some(karney_inverse(fix1, fix2))
+    }
+}
+
+// The great circle distance on the FAI sphere, as required for record and badge claims
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FaiSphere;
+
+impl DistanceModel for FaiSphere {
+    fn inverse<F: Float, T: Point>(&self, fix1: &T, fix2: &T) -> Option<(F, F)> {
+        let n = F::from_f64;
+        let (lat1, lat2) = (
+            n(fix1.latitude()).to_radians(),
+            n(fix2.latitude()).to_radians(),
+        );
+        let delta_lon = n(fix2.longitude() - fix1.longitude()).to_radians();
+        let delta_lat = lat2 - lat1;
+        // haversine formula, which is well conditioned for short distances
+        let h = (delta_lat / n(2.)).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (delta_lon / n(2.)).sin().powi(2);
+        let distance = n(2. * FAI_EARTH_RADIUS) * h.sqrt().min(F::one()).asin();
+        let bearing = (delta_lon.sin() * lat2.cos())
+            .atan2(lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos());
+        Some((distance, (bearing.to_degrees() + n(360.)) % n(360.)))
+    }
+}
+
+// The flat projection of the route, which the optimizations use to compare paths
+#[derive(Debug, Clone, Copy)]
+pub struct Flat {
+    center: (f64, f64),
+}
+
+impl Flat {
+    // Use the same projection as flat::to_flat_points for this route
+    pub fn new<T: Point>(route: &[T]) -> Self {
+        Flat {
+            center: projection_center(route).unwrap_or_default(),
+        }
+    }
+}
+
+impl DistanceModel for Flat {
+    fn inverse<F: Float, T: Point>(&self, fix1: &T, fix2: &T) -> Option<(F, F)> {
+        let n = F::from_f64;
+        let projection = FlatProjection::new(n(self.center.0), n(self.center.1));
+        let project = |fix: &T| projection.project(n(fix.longitude()), n(fix.latitude()));
+        let (distance, bearing) = project(fix1).distance_bearing(&project(fix2));
+        Some((distance, (bearing + n(360.)) % n(360.)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PointImpl;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn point(latitude: f64, longitude: f64) -> PointImpl {
+        PointImpl {
+            latitude,
+            longitude,
+            altitude: 0,
+            timestamp: None,
+            engine_noise: None,
+        }
+    }
+
+    #[test]
+    fn models_agree_on_short_distances() {
+        let route = [point(50.0, 10.0), point(50.5, 11.0)];
+        let (vincenty, vincenty_bearing): (f64, f64) =
+            Vincenty.inverse(&route[0], &route[1]).unwrap();
+        for (distance, bearing) in [
+            FaiSphere.inverse::<f64, _>(&route[0], &route[1]).unwrap(),
+            Flat::new(&route).inverse(&route[0], &route[1]).unwrap(),
+        ] {
+            // the models differ by a few hundred meters on 88 km
+            assert_approx_eq!(distance, vincenty, 0.5);
+            assert_approx_eq!(bearing, vincenty_bearing, 0.5);
+        }
+    }
+
+    #[test]
+    fn fai_sphere_matches_great_circle() {
+        // a quarter of the equator and a meridian from the equator to the pole
+        let (distance, bearing): (f64, f64) = FaiSphere
+            .inverse(&point(0.0, 0.0), &point(0.0, 90.0))
+            .unwrap();
+        assert_approx_eq!(
+            distance,
+            FAI_EARTH_RADIUS * std::f64::consts::FRAC_PI_2,
+            1e-6
+        );
+        assert_approx_eq!(bearing, 90.0, 1e-9);
+        let (distance, bearing): (f64, f64) = FaiSphere
+            .inverse(&point(0.0, 10.0), &point(90.0, 10.0))
+            .unwrap();
+        assert_approx_eq!(
+            distance,
+            FAI_EARTH_RADIUS * std::f64::consts::FRAC_PI_2,
+            1e-6
+        );
+        assert_approx_eq!(bearing, 0.0, 1e-9);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use crate::point::Point;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScoreError {
+    // The route does not contain any points
+    EmptyRoute,
+    // The route has less points than the optimization requires
+    TooFewPoints { points: usize, required: usize },
+    // The latitude or longitude of the point at this index is NaN or infinite
+    NonFiniteCoordinates { index: usize },
+    // The point at this index has no timestamp, but the constraints depend on time
+    MissingTimestamp { index: usize },
+    // No route complies with the constraints of the optimization
+    NoValidSolution,
+    // The budget of the optimization ran out before a valid route was found
+    BudgetExhausted,
+    // The optimization was cancelled before a valid route was found
+    Cancelled,
+    // The distance model does not converge for the leg between the points at these indices
+    NoConvergence { start: usize, stop: usize },
+}
+
+impl fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScoreError::EmptyRoute => write!(f, "the route does not contain any points"),
+            ScoreError::TooFewPoints { points, required } => write!(
+                f,
+                "the route has {} points, but at least {} are required",
+                points, required
+            ),
+            ScoreError::NonFiniteCoordinates { index } => {
+                write!(f, "the point at index {} has non-finite coordinates", index)
+            }
+            ScoreError::MissingTimestamp { index } => {
+                write!(f, "the point at index {} has no timestamp", index)
+            }
+            ScoreError::NoValidSolution => write!(f, "no route complies with the constraints"),
+            ScoreError::BudgetExhausted => {
+                write!(f, "the budget ran out before a valid route was found")
+            }
+            ScoreError::Cancelled => {
+                write!(
+                    f,
+                    "the optimization was cancelled before a valid route was found"
+                )
+            }
+            ScoreError::NoConvergence { start, stop } => write!(
+                f,
+                "the distance between the points at index {} and {} does not converge",
+                start, stop
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScoreError {}
+
+#[derive(Debug)]
+pub enum IgcError {
+    // The file can not be read
+    Io(std::io::Error),
+    // The record in this line (counted from 1) is malformed
+    MalformedRecord { line: usize, reason: &'static str },
+}
+
+impl fmt::Display for IgcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IgcError::Io(err) => write!(f, "the file can not be read: {}", err),
+            IgcError::MalformedRecord { line, reason } => {
+                write!(f, "malformed record in line {}: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IgcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IgcError::Io(err) => Some(err),
+            IgcError::MalformedRecord { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for IgcError {
+    fn from(err: std::io::Error) -> Self {
+        IgcError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum GpxError {
+    // The file can not be read
+    Io(std::io::Error),
+    // The track point in this line (counted from 1) is malformed
+    MalformedTrackPoint { line: usize, reason: &'static str },
+}
+
+impl fmt::Display for GpxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpxError::Io(err) => write!(f, "the file can not be read: {}", err),
+            GpxError::MalformedTrackPoint { line, reason } => {
+                write!(f, "malformed track point in line {}: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GpxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GpxError::Io(err) => Some(err),
+            GpxError::MalformedTrackPoint { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GpxError {
+    fn from(err: std::io::Error) -> Self {
+        GpxError::Io(err)
+    }
+}
+
+// Check that the route can be optimized, i.e. it has at least the required number of points
+// and all coordinates are finite
+pub fn check_route<T: Point>(route: &[T], required: usize) -> Result<(), ScoreError> {
+    if route.is_empty() {
+        return Err(ScoreError::EmptyRoute);
+    }
+    if route.len() < required {
+        return Err(ScoreError::TooFewPoints {
+            points: route.len(),
+            required,
+        });
+    }
+    match route
+        .iter()
+        .position(|point| !point.latitude().is_finite() || !point.longitude().is_finite())
+    {
+        Some(index) => Err(ScoreError::NonFiniteCoordinates { index }),
+        None => Ok(()),
+    }
+}
+
+// Check that all points of the route have a timestamp
+pub fn check_timestamps<T: Point>(route: &[T]) -> Result<(), ScoreError> {
+    match route.iter().position(|point| point.timestamp().is_none()) {
+        Some(index) => Err(ScoreError::MissingTimestamp { index }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PointImpl;
+
+    fn point(latitude: f64, longitude: f64) -> PointImpl {
+        PointImpl {
+            latitude,
+            longitude,
+            altitude: 0,
+            timestamp: None,
+            engine_noise: None,
+        }
+    }
+
+    #[test]
+    fn empty_route_is_rejected() {
+        let route: Vec<PointImpl> = Vec::new();
+        assert_eq!(check_route(&route, 0), Err(ScoreError::EmptyRoute));
+    }
+
+    #[test]
+    fn short_route_is_rejected() {
+        let route = [point(0.0, 0.0), point(1.0, 1.0)];
+        assert_eq!(
+            check_route(&route, 3),
+            Err(ScoreError::TooFewPoints {
+                points: 2,
+                required: 3
+            })
+        );
+        assert_eq!(check_route(&route, 2), Ok(()));
+    }
+
+    #[test]
+    fn non_finite_coordinates_are_rejected() {
+        let route = [
+            point(0.0, 0.0),
+            point(1.0, f64::NAN),
+            point(f64::INFINITY, 0.0),
+        ];
+        assert_eq!(
+            check_route(&route, 1),
+            Err(ScoreError::NonFiniteCoordinates { index: 1 })
+        );
+    }
+
+    #[test]
+    fn missing_timestamps_are_rejected() {
+        let mut route = [point(0.0, 0.0), point(1.0, 1.0)];
+        route[0].timestamp = Some(0);
+        assert_eq!(
+            check_timestamps(&route),
+            Err(ScoreError::MissingTimestamp { index: 1 })
+        );
+        route[1].timestamp = Some(1);
+        assert_eq!(check_timestamps(&route), Ok(()));
+    }
+}
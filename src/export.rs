@@ -0,0 +1,183 @@
+// Export an optimized route for maps: GeoJSON and KML with the scored path as a line
+// and the turnpoints as points. Distances are in km, altitudes in meters.
+
+use std::fmt::Write;
+
+use crate::float::Float;
+use crate::point::Point;
+use crate::result::OptimizationResult;
+
+// Return a GeoJSON FeatureCollection with a LineString of the scored path and a Point for every
+// turnpoint. The LineString has the total distance and the distances of the legs as properties,
+// every turnpoint has its index in the route and the distance of the leg which ends at it.
+pub fn to_geojson<F: Float, T: Point>(route: &[T], result: &OptimizationResult<F>) -> String {
+    let coordinates: Vec<String> = result
+        .path
+        .iter()
+        .map(|&index| geojson_coordinates(&route[index]))
+        .collect();
+    let legs: Vec<String> = result
+        .legs
+        .iter()
+        .map(|leg| leg.distance.to_string())
+        .collect();
+
+    let mut features = vec![format!(
+        r#"{{"type":"Feature","geometry":{{"type":"LineString","coordinates":[{}]}},"properties":{{"distance":{},"legs":[{}]}}}}"#,
+        coordinates.join(","),
+        result.distance,
+        legs.join(",")
+    )];
+    for (turnpoint, &index) in result.path.iter().enumerate() {
+        let mut properties = format!(
+            r#""name":"TP{}","index":{},"altitude":{}"#,
+            turnpoint,
+            index,
+            route[index].altitude()
+        );
+        if let Some(timestamp) = route[index].timestamp() {
+            write!(properties, r#","time":{}"#, timestamp).unwrap();
+        }
+        // a result which was not built by OptimizationResult::new may lack legs
+        if let Some(leg) = turnpoint
+            .checked_sub(1)
+            .and_then(|leg| result.legs.get(leg))
+        {
+            write!(properties, r#","leg_distance":{}"#, leg.distance).unwrap();
+        }
+        features.push(format!(
+            r#"{{"type":"Feature","geometry":{{"type":"Point","coordinates":{}}},"properties":{{{}}}}}"#,
+            coordinates[turnpoint], properties
+        ));
+    }
+    format!(
+        r#"{{"type":"FeatureCollection","features":[{}]}}"#,
+        features.join(",")
+    )
+}
+
+// Return a KML document with a LineString placemark of the scored path and a placemark for every
+// turnpoint. The descriptions contain the distances of the legs.
+pub fn to_kml<F: Float, T: Point>(route: &[T], result: &OptimizationResult<F>) -> String {
+    let coordinates: Vec<String> = result
+        .path
+        .iter()
+        .map(|&index| kml_coordinates(&route[index]))
+        .collect();
+    let legs: Vec<String> = result
+        .legs
+        .iter()
+        .enumerate()
+        .map(|(number, leg)| format!("Leg {}: {:.2} km", number + 1, leg.distance))
+        .collect();
+
+    let mut kml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<kml xmlns="http://www.opengis.net/kml/2.2"><Document>"#,
+        "\n"
+    ));
+    writeln!(
+        kml,
+        "<Placemark><name>{:.2} km</name><description>{}</description><LineString><altitudeMode>absolute</altitudeMode><coordinates>{}</coordinates></LineString></Placemark>",
+        result.distance,
+        legs.join(", "),
+        coordinates.join(" ")
+    )
+    .unwrap();
+    for (turnpoint, &index) in result.path.iter().enumerate() {
+        let description = match turnpoint.checked_sub(1).and_then(|leg| legs.get(leg)) {
+            Some(leg) => format!("Index {}, {}", index, leg),
+            None => format!("Index {}", index),
+        };
+        writeln!(
+            kml,
+            "<Placemark><name>TP{}</name><description>{}</description><Point><altitudeMode>absolute</altitudeMode><coordinates>{}</coordinates></Point></Placemark>",
+            turnpoint, description, coordinates[turnpoint]
+        )
+        .unwrap();
+    }
+    kml.push_str("</Document></kml>\n");
+    kml
+}
+
+fn geojson_coordinates<T: Point>(point: &T) -> String {
+    format!(
+        "[{},{},{}]",
+        point.longitude(),
+        point.latitude(),
+        point.altitude()
+    )
+}
+
+fn kml_coordinates<T: Point>(point: &T) -> String {
+    format!(
+        "{},{},{}",
+        point.longitude(),
+        point.latitude(),
+        point.altitude()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::Vincenty;
+    use crate::point::PointImpl;
+
+    fn route() -> Vec<PointImpl> {
+        [(50.0, 10.0, 500), (51.0, 10.0, 1500), (51.0, 11.0, 1000)]
+            .iter()
+            .map(|&(latitude, longitude, altitude)| PointImpl {
+                latitude,
+                longitude,
+                altitude,
+                timestamp: None,
+                engine_noise: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn geojson_contains_path_and_turnpoints() {
+        let route = route();
+        let result: OptimizationResult =
+            OptimizationResult::new(vec![0, 1, 2], &route, &Vincenty).unwrap();
+        let geojson = to_geojson(&route, &result);
+        assert!(geojson.starts_with(r#"{"type":"FeatureCollection","features":[{"type":"Feature","geometry":{"type":"LineString","coordinates":[[10,50,500],[10,51,1500],[11,51,1000]]}"#));
+        assert_eq!(geojson.matches(r#""type":"Point""#).count(), 3);
+        assert!(geojson.contains(&format!(
+            r#""name":"TP2","index":2,"altitude":1000,"leg_distance":{}"#,
+            result.legs[1].distance
+        )));
+        assert_eq!(geojson.matches('{').count(), geojson.matches('}').count());
+    }
+
+    #[test]
+    fn kml_contains_path_and_turnpoints() {
+        let route = route();
+        let result: OptimizationResult =
+            OptimizationResult::new(vec![0, 1, 2], &route, &Vincenty).unwrap();
+        let kml = to_kml(&route, &result);
+        assert!(kml.contains("<coordinates>10,50,500 10,51,1500 11,51,1000</coordinates>"));
+        assert_eq!(kml.matches("<Placemark>").count(), 4);
+        assert!(kml.contains(&format!(
+            "<name>TP1</name><description>Index 1, Leg 1: {:.2} km</description>",
+            result.legs[0].distance
+        )));
+        assert!(kml.ends_with("</Document></kml>\n"));
+    }
+
+    #[test]
+    fn missing_legs_are_left_out() {
+        let route = route();
+        let mut result: OptimizationResult =
+            OptimizationResult::new(vec![0, 1, 2], &route, &Vincenty).unwrap();
+        result.legs.truncate(1);
+        let geojson = to_geojson(&route, &result);
+        assert_eq!(geojson.matches(r#""leg_distance""#).count(), 1);
+        assert!(geojson.contains(r#""name":"TP2","index":2,"altitude":1000}"#));
+        let kml = to_kml(&route, &result);
+        assert!(kml.contains("<name>TP2</name><description>Index 2</description>"));
+    }
+}
@@ -1,33 +1,45 @@
 use flat_projection::{FlatPoint, FlatProjection};
 
+use crate::float::Float;
 use crate::parallel::*;
 use crate::point::Point;
 
 // Calculate the mean of two angles, output range is [-180, 180]
-fn circ_mean(a: f32, b: f32) -> f32 {
+// Opposite angles have no unique mean, so the direction 90 degrees before a is returned
+// instead of leaving the decision to the rounding of sin and cos
+fn circ_mean(a: f64, b: f64) -> f64 {
     let a = a.to_radians();
     let b = b.to_radians();
     let x = (a.cos() + b.cos()) / 2.;
     let y = (a.sin() + b.sin()) / 2.;
+    if x.hypot(y) < 1e-9 {
+        let before = a - std::f64::consts::FRAC_PI_2;
+        return before.sin().atan2(before.cos()).to_degrees();
+    }
     y.atan2(x).to_degrees()
 }
 
 /// Projects all geographic points onto a flat surface for faster geodesic calculation
 ///
-pub fn to_flat_points<T: Point>(route: &[T]) -> Vec<FlatPoint<f32>> {
-    let center = route.center().unwrap();
-    let proj = FlatProjection::new(center.0, center.1);
+pub fn to_flat_points<F: Float, T: Point>(route: &[T]) -> Vec<FlatPoint<F>> {
+    let center = projection_center(route).unwrap();
+    let proj = FlatProjection::new(F::from_f64(center.0), F::from_f64(center.1));
 
     opt_par_iter(route)
-        .map(|fix| proj.project(fix.longitude(), fix.latitude()))
+        .map(|fix| proj.project(F::from_f64(fix.longitude()), F::from_f64(fix.latitude())))
         .collect()
 }
 
+// Return the (longitude, latitude) of the center of the projection, None for an empty route
+pub fn projection_center<T: Point>(route: &[T]) -> Option<(f64, f64)> {
+    route.center()
+}
+
 struct BBox {
-    lon_min: f32,
-    lon_max: f32,
-    lat_min: f32,
-    lat_max: f32,
+    lon_min: f64,
+    lon_max: f64,
+    lat_min: f64,
+    lat_max: f64,
 }
 
 impl BBox {
@@ -38,10 +50,10 @@ impl BBox {
         self.lat_max = self.lat_max.max(point.latitude());
     }
 
-    fn center_lat(&self) -> f32 {
+    fn center_lat(&self) -> f64 {
         (self.lat_min + self.lat_max) / 2.
     }
-    fn center_lon(&self) -> f32 {
+    fn center_lon(&self) -> f64 {
         circ_mean(self.lon_min, self.lon_max)
     }
 }
@@ -72,17 +84,17 @@ impl<T: Point> TryFrom<&[T]> for BBox {
 }
 
 trait Center {
-    fn center(&self) -> Option<(f32, f32)>;
+    fn center(&self) -> Option<(f64, f64)>;
 }
 
 impl Center for BBox {
-    fn center(&self) -> Option<(f32, f32)> {
+    fn center(&self) -> Option<(f64, f64)> {
         Some((self.center_lon(), self.center_lat()))
     }
 }
 
 impl<T: Point> Center for [T] {
-    fn center(&self) -> Option<(f32, f32)> {
+    fn center(&self) -> Option<(f64, f64)> {
         BBox::try_from(self).ok()?.center()
     }
 }
@@ -93,11 +105,11 @@ mod tests {
     use assert_approx_eq::assert_approx_eq;
 
     impl Point for (f64, f64) {
-        fn longitude(&self) -> f32 {
-            self.0 as f32
+        fn longitude(&self) -> f64 {
+            self.0
         }
-        fn latitude(&self) -> f32 {
-            self.1 as f32
+        fn latitude(&self) -> f64 {
+            self.1
         }
         fn altitude(&self) -> i16 {
             0
@@ -130,7 +142,7 @@ mod tests {
         let points: Vec<(f64, f64)> = vec![];
         assert!(points.center().is_none());
 
-        let points = vec![(10., 50.0), (11., 51.), (12., 52.), (-10., -5.), (11., -5.)];
+        let points = [(10., 50.0), (11., 51.), (12., 52.), (-10., -5.), (11., -5.)];
 
         let bbox = BBox::try_from(&points[..]).unwrap();
         assert_approx_eq!(bbox.lon_min, -10.);
@@ -154,7 +166,7 @@ mod tests {
 
     #[test]
     fn bbox_longitude_does_not_overflow() {
-        let points = vec![(179., 50.), (-179., 50.)];
+        let points = [(179., 50.), (-179., 50.)];
         let center = points.center().unwrap();
         assert_approx_eq!(center.0, 180.);
     }
@@ -0,0 +1,337 @@
+// Read flights from IGC files: the fixes (B records) together with the extensions defined in the
+// I record (e.g. engine noise level ENL and means of propulsion MOP), the header fields (H records)
+// and the task declaration (C records). All other records are ignored.
+
+use std::collections::HashMap;
+use std::fs;
+
+use igc::records::{BRecord, CRecordDeclaration, CRecordTurnpoint, HRecord, IRecord};
+use igc::util::ParseError;
+
+use crate::error::IgcError;
+use crate::point::Point;
+
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Which of the two altitudes of a fix is used for the optimization
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AltitudePolicy {
+    // Use the pressure altitude, unless the logger has no pressure sensor (all pressure altitudes are zero)
+    #[default]
+    Auto,
+    Pressure,
+    Gnss,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub latitude: f64,
+    pub longitude: f64,
+    // The altitude chosen by the AltitudePolicy
+    pub altitude: i16,
+    pub pressure_altitude: i16,
+    pub gnss_altitude: i16,
+    // Seconds since midnight (UTC) of the first fix, keeps increasing after midnight
+    pub timestamp: i64,
+    // false if the logger reported a navigation warning (2D fix or no GNSS data)
+    pub valid: bool,
+    // Engine noise level, if recorded
+    pub enl: Option<u16>,
+    // Means of propulsion, e.g. the current of an electric engine, if recorded
+    pub mop: Option<u16>,
+}
+
+impl Point for Fix {
+    fn latitude(&self) -> f64 {
+        self.latitude
+    }
+    fn longitude(&self) -> f64 {
+        self.longitude
+    }
+    fn altitude(&self) -> i16 {
+        self.altitude
+    }
+    fn timestamp(&self) -> Option<i64> {
+        Some(self.timestamp)
+    }
+    // The higher value of ENL and MOP
+    fn engine_noise(&self) -> Option<u16> {
+        self.enl.max(self.mop)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turnpoint {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub name: Option<String>,
+}
+
+// The task declared before the flight. The turnpoints are stored as declared: takeoff, start,
+// the turnpoints, finish and landing. Takeoff and landing are often left empty (at 0/0).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub name: Option<String>,
+    pub turnpoints: Vec<Turnpoint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Flight {
+    // The header fields by their three letter mnemonic, e.g. DTE (date), PLT (pilot) or GTY (glider type)
+    pub headers: HashMap<String, String>,
+    // The mnemonics of all extensions of the fixes
+    pub extensions: Vec<String>,
+    pub task: Option<Task>,
+    pub fixes: Vec<Fix>,
+}
+
+impl Flight {
+    // Read and parse an IGC file. Invalid UTF-8 (e.g. in the name of the pilot) is replaced.
+    pub fn read<P: AsRef<std::path::Path>>(
+        path: P,
+        policy: AltitudePolicy,
+    ) -> Result<Flight, IgcError> {
+        let content = fs::read(path)?;
+        Flight::parse(&String::from_utf8_lossy(&content), policy)
+    }
+
+    // Parse the content of an IGC file. A malformed B, C, H or I record results in an error.
+    pub fn parse(content: &str, policy: AltitudePolicy) -> Result<Flight, IgcError> {
+        let mut headers = HashMap::new();
+        let mut extensions: Vec<(String, usize, usize)> = Vec::new();
+        let mut task: Option<Task> = None;
+        let mut fixes: Vec<Fix> = Vec::new();
+        let mut day_offset = 0;
+
+        for (index, line) in content.lines().enumerate() {
+            let malformed = |reason| IgcError::MalformedRecord {
+                line: index + 1,
+                reason,
+            };
+            let parse_error = |err| malformed(describe(err));
+            match line.as_bytes().first() {
+                Some(b'B') => {
+                    let record = BRecord::parse(line).map_err(parse_error)?;
+                    let extension = |mnemonic| -> Result<Option<u16>, IgcError> {
+                        let Some((_, start, end)) = extensions.iter().find(|e| e.0 == mnemonic)
+                        else {
+                            return Ok(None);
+                        };
+                        match line.get(start.saturating_sub(1)..*end) {
+                            Some(value) => value
+                                .parse()
+                                .map(Some)
+                                .map_err(|_| malformed("invalid extension value")),
+                            // a record which is too short for a declared extension is
+                            // truncated, it does not mean that the fix has no value
+                            None => Err(malformed("syntax error")),
+                        }
+                    };
+
+                    let mut timestamp =
+                        record.timestamp.seconds_since_midnight() as i64 + day_offset;
+                    if let Some(previous) = fixes.last() {
+                        // the flight continues after midnight (UTC)
+                        if timestamp < previous.timestamp - SECONDS_PER_DAY / 2 {
+                            day_offset += SECONDS_PER_DAY;
+                            timestamp += SECONDS_PER_DAY;
+                        }
+                    }
+                    fixes.push(Fix {
+                        latitude: record.pos.lat.into(),
+                        longitude: record.pos.lon.into(),
+                        altitude: record.pressure_alt,
+                        pressure_altitude: record.pressure_alt,
+                        gnss_altitude: record.gps_alt,
+                        timestamp,
+                        valid: record.fix_valid == igc::records::FixValid::Valid,
+                        enl: extension("ENL")?,
+                        mop: extension("MOP")?,
+                    });
+                }
+                Some(b'I') => {
+                    let record = IRecord::parse(line).map_err(parse_error)?;
+                    extensions = record
+                        .0
+                        .extensions
+                        .iter()
+                        .map(|e| {
+                            (
+                                e.mnemonic.to_string(),
+                                e.start_byte as usize,
+                                e.end_byte as usize,
+                            )
+                        })
+                        .collect();
+                }
+                Some(b'H') => {
+                    let record = HRecord::parse(line).map_err(parse_error)?;
+                    headers.insert(record.mnemonic.to_string(), record.data.trim().to_string());
+                }
+                // the first C record declares the task, the following ones its turnpoints
+                Some(b'C') => match task.as_mut() {
+                    None => {
+                        let record = CRecordDeclaration::parse(line).map_err(parse_error)?;
+                        task = Some(Task {
+                            name: non_empty(record.task_name),
+                            turnpoints: Vec::new(),
+                        });
+                    }
+                    Some(task) => {
+                        let record = CRecordTurnpoint::parse(line).map_err(parse_error)?;
+                        task.turnpoints.push(Turnpoint {
+                            latitude: record.position.lat.into(),
+                            longitude: record.position.lon.into(),
+                            name: non_empty(record.turnpoint_name),
+                        });
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let use_gnss = match policy {
+            AltitudePolicy::Auto => fixes.iter().all(|fix| fix.pressure_altitude == 0),
+            AltitudePolicy::Pressure => false,
+            AltitudePolicy::Gnss => true,
+        };
+        if use_gnss {
+            fixes
+                .iter_mut()
+                .for_each(|fix| fix.altitude = fix.gnss_altitude);
+        }
+
+        Ok(Flight {
+            headers,
+            extensions: extensions.into_iter().map(|e| e.0).collect(),
+            task,
+            fixes,
+        })
+    }
+
+    // Return the header field with the given three letter mnemonic
+    pub fn header(&self, mnemonic: &str) -> Option<&str> {
+        self.headers.get(mnemonic).map(|data| data.as_str())
+    }
+}
+
+fn non_empty(name: Option<&str>) -> Option<String> {
+    name.map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+fn describe(err: ParseError) -> &'static str {
+    match err {
+        ParseError::SyntaxError => "syntax error",
+        ParseError::NonASCIICharacters => "non-ASCII characters",
+        ParseError::NumberOutOfRange => "number out of range",
+        ParseError::BadExtension => "invalid extension",
+        ParseError::MissingExtension => "missing extension",
+        ParseError::IOError(_) | ParseError::Utf8Error(_) => "invalid encoding",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    const HEADER: &str = "AXXX001\nHFDTE170623\nI023638FXA3941ENL\n";
+
+    #[test]
+    fn parse_fixture() {
+        let content = include_str!("../fixtures/2023-06-17_288167.igc");
+        let flight = Flight::parse(content, AltitudePolicy::default()).unwrap();
+        let records = content.lines().filter(|l| l.starts_with('B')).count();
+        assert_eq!(flight.fixes.len(), records);
+        assert_eq!(flight.header("DTE"), Some("170623"));
+        assert_eq!(flight.header("GTY"), Some("LS 10-s-18"));
+        assert_eq!(flight.header("PLT"), Some(""));
+        assert_eq!(flight.extensions[..2], ["FXA", "ENL"]);
+
+        let task = flight.task.unwrap();
+        assert_eq!(task.name, None);
+        assert_eq!(task.turnpoints.len(), 7);
+        assert_eq!(task.turnpoints[1].name.as_deref(), Some("NASTAETTEN"));
+        assert_approx_eq!(task.turnpoints[1].latitude, 50.198, 0.001);
+
+        let fix = &flight.fixes[0];
+        assert!(fix.enl.is_some());
+        assert_eq!(fix.mop, None);
+        assert_eq!(fix.altitude, fix.pressure_altitude);
+        assert!(flight
+            .fixes
+            .windows(2)
+            .all(|fixes| fixes[0].timestamp <= fixes[1].timestamp));
+    }
+
+    #[test]
+    fn parse_fix() {
+        let content = format!("{}B1010005000000N01000000EA0010000150010000\n", HEADER);
+        let flight = Flight::parse(&content, AltitudePolicy::Gnss).unwrap();
+        let fix = &flight.fixes[0];
+        assert_eq!((fix.latitude, fix.longitude), (50.0, 10.0));
+        assert_eq!(fix.timestamp, 10 * 3600 + 10 * 60);
+        assert_eq!((fix.pressure_altitude, fix.gnss_altitude), (100, 150));
+        assert_eq!(fix.altitude, 150);
+        assert_eq!(fix.enl, Some(0));
+        assert!(fix.valid);
+    }
+
+    #[test]
+    fn auto_policy_falls_back_to_gnss() {
+        let content = format!(
+            "{}B1010005000000N01000000EA0000000150010000\nB1010015000000N01000000EV0000000160010000\n",
+            HEADER
+        );
+        let flight = Flight::parse(&content, AltitudePolicy::Auto).unwrap();
+        assert_eq!(flight.fixes[0].altitude, 150);
+        assert!(!flight.fixes[1].valid);
+        let flight = Flight::parse(&content, AltitudePolicy::Pressure).unwrap();
+        assert_eq!(flight.fixes[1].altitude, 0);
+    }
+
+    #[test]
+    fn timestamps_continue_after_midnight() {
+        let content = format!(
+            "{}B2359595000000N01000000EA0010000150010000\nB0000015000000N01000000EA0010000150010000\n",
+            HEADER
+        );
+        let flight = Flight::parse(&content, AltitudePolicy::default()).unwrap();
+        assert_eq!(flight.fixes[1].timestamp - flight.fixes[0].timestamp, 2);
+    }
+
+    #[test]
+    fn malformed_records_are_errors() {
+        for (record, reason) in [
+            ("B1010005000000N01000000EA001000015", "syntax error"),
+            ("B1010005000000N01000000EX0010000150010000", "syntax error"),
+            (
+                "B1010005000000N01000000EA00100001500100XX",
+                "invalid extension value",
+            ),
+            ("B1010005000000N01000000EA00100001500100", "syntax error"),
+            ("I02363", "syntax error"),
+        ] {
+            let content = format!("{}{}\n", HEADER, record);
+            match Flight::parse(&content, AltitudePolicy::default()) {
+                Err(IgcError::MalformedRecord {
+                    line,
+                    reason: found,
+                }) => {
+                    assert_eq!((line, found), (4, reason), "{}", record)
+                }
+                other => panic!("{} was parsed as {:?}", record, other),
+            }
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(matches!(
+            Flight::read("fixtures/missing.igc", AltitudePolicy::default()),
+            Err(IgcError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,29 @@
+// The floating point type of the geometry. f32 is fast and needs half the memory, which is good
+// enough for previews. f64 avoids errors in the order of meters, e.g. for final rankings.
+// The optimizations are generic over the type, it is chosen by the type of their distance
+// argument (break_at or closing_distance).
+
+use std::fmt::{Debug, Display};
+use std::iter::Sum;
+use std::ops::AddAssign;
+
+use ord_subset::OrdSubset;
+
+pub trait Float:
+    num_traits::Float + OrdSubset + AddAssign + Sum + Debug + Display + Send + Sync + 'static
+{
+    // Convert a constant or a coordinate, rounding to the nearest value for f32
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Float for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl Float for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
@@ -1,114 +1,627 @@
 use flat_projection::FlatPoint;
 
-use crate::cache::{Cache, CacheItem};
+use crate::cache::{Cache, CacheItem, CacheStats};
+use crate::distance::DistanceModel;
+use crate::error::{check_route, ScoreError};
 use crate::flat::to_flat_points;
-use crate::graph::Graph;
+use crate::float::Float;
+use crate::graph::{Graph, StartCandidate};
+pub use crate::matrix::half_dist_matrix;
+use crate::matrix::{DistanceMatrix, Distances};
 use crate::parallel::*;
-use crate::point::Point;
-use crate::result::{Bound, OptimizationResult};
+use crate::point::{ApproxDistance, Constraints, Path, Point, Valid};
+use crate::reduce::reduce;
+use crate::result::{AnytimeResult, Bound, OptimizationResult, Status};
+use std::time::{Duration, Instant};
 
 // Find the optimal set of (legs + 1) turnpoints, such that the sum of the inter turnpoints distances is maximized.
+// The route has to comply with the constraints, e.g. the altitude loss between the first and the last turnpoint is
+// restricted by an AltitudeRule and only fixes within a time window are used.
 // Break if no solution above break_at km an be found
-pub fn optimize<T: Point>(route: &[T], break_at: f32, legs: usize) -> Option<OptimizationResult> {
+//
+// All distances are calculated with the float type of break_at: f32 for fast previews,
+// f64 for exact scoring (which doubles the memory of the distance matrix).
+// The search compares paths in the flat projection, the distance model measures the paths
+// found by sliding the first and the last turnpoint and the legs of the result.
+pub fn optimize<F: Float, T: Point, C: Into<Constraints>, M: DistanceModel>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: C,
+    model: &M,
+) -> Result<OptimizationResult<F>, ScoreError> {
+    optimize_within(route, break_at, legs, constraints, model, Budget::default())
+        .map(|anytime| anytime.result)
+}
+
+// The limits of an optimization. The first graph over all fixes is always built,
+// so the time of an optimization can exceed the time budget on long tracks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    // Stop after this time, measured from the start of the optimization
+    pub time: Option<Duration>,
+    // Stop after this number of start candidates
+    pub candidates: Option<usize>,
+    // Store the distance matrix only if it needs at most this number of bytes, otherwise calculate
+    // the distances on the fly. The result is the same, but every graph takes longer to build.
+    pub memory: Option<usize>,
+}
+
+impl Budget {
+    fn exhausted(&self, started: Instant, candidates: usize) -> bool {
+        self.time.is_some_and(|time| started.elapsed() >= time)
+            || self.candidates.is_some_and(|max| candidates >= max)
+    }
+}
+
+// Like optimize, but stop when the budget runs out and return the best valid path found so far
+// together with an upper bound of the optimal distance
+pub fn optimize_within<F: Float, T: Point, C: Into<Constraints>, M: DistanceModel>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: C,
+    model: &M,
+    budget: Budget,
+) -> Result<AnytimeResult<F>, ScoreError> {
+    optimize_with_progress(route, break_at, legs, constraints, model, budget, |_| true)
+}
+
+// The state of the search over the start candidates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress<F> {
+    // The start candidates which still have to be checked, including the current one
+    pub remaining: usize,
+    // The flat distance of the best valid path so far, negative infinity without one
+    pub best_distance: F,
+    // The start candidates which have been checked
+    pub checked: usize,
+    // The checked candidates which the cache ruled out
+    pub pruned: usize,
+    // The checked candidates which needed a full Graph::for_candidate build
+    pub built: usize,
+    // The hits, misses and compared items of the cache
+    pub cache: CacheStats,
+}
+
+// Like optimize_within, but report the progress before every start candidate.
+// The search is cancelled if the callback returns false, the result is the best valid path so far.
+pub fn optimize_with_progress<F, T, C, M, P>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: C,
+    model: &M,
+    budget: Budget,
+    progress: P,
+) -> Result<AnytimeResult<F>, ScoreError>
+where
+    F: Float,
+    T: Point,
+    C: Into<Constraints>,
+    M: DistanceModel,
+    P: FnMut(&Progress<F>) -> bool,
+{
+    optimize_with(
+        route,
+        break_at,
+        legs,
+        constraints,
+        model,
+        budget,
+        Strategy::default(),
+        progress,
+    )
+}
+
+// Like optimize_within, but solve the track downsampled to every step-th fix first and refine the
+// coarse turnpoints with the fixes around them. The refined path is a lower bound of the optimum,
+// so it rules out many start candidates of the full track before their graphs are built.
+// The result is the same as without the coarse stage.
+pub fn optimize_coarse_to_fine<F: Float, T: Point, C: Into<Constraints>, M: DistanceModel>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: C,
+    model: &M,
+    budget: Budget,
+    step: usize,
+) -> Result<AnytimeResult<F>, ScoreError> {
+    optimize_with(
+        route,
+        break_at,
+        legs,
+        constraints,
+        model,
+        budget,
+        Strategy {
+            coarse: Some(step),
+            ..Default::default()
+        },
+        |_| true,
+    )
+}
+
+// Like optimize_within, but build the graphs of up to batch start candidates at once.
+// All candidates of a batch are checked against the best valid path of the previous batches
+// and their results are merged in the order of the sequential search, so the result is the same
+// as with optimize_within. Candidates which the sequential search would have skipped after an
+// improvement within the batch still count towards the candidate budget.
+pub fn optimize_in_parallel<F: Float, T: Point, C: Into<Constraints>, M: DistanceModel>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: C,
+    model: &M,
+    budget: Budget,
+    batch: usize,
+) -> Result<AnytimeResult<F>, ScoreError> {
+    optimize_with(
+        route,
+        break_at,
+        legs,
+        constraints,
+        model,
+        budget,
+        Strategy {
+            batch: batch.max(1),
+            ..Default::default()
+        },
+        |_| true,
+    )
+}
+
+// How the start candidates are searched: the step of the coarse stage, if any,
+// and the number of candidates whose graphs are built at once
+#[derive(Debug, Clone, Copy)]
+struct Strategy {
+    coarse: Option<usize>,
+    batch: usize,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy {
+            coarse: None,
+            batch: 1,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn optimize_with<F, T, C, M, P>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: C,
+    model: &M,
+    budget: Budget,
+    strategy: Strategy,
+    mut progress: P,
+) -> Result<AnytimeResult<F>, ScoreError>
+where
+    F: Float,
+    T: Point,
+    C: Into<Constraints>,
+    M: DistanceModel,
+    P: FnMut(&Progress<F>) -> bool,
+{
+    let started = Instant::now();
+    let constraints = constraints.into();
+    check_route(route, legs + 1)?;
+    let window = constraints.scoring_window(route)?;
+    check_route(&route[window.clone()], legs + 1)?;
+
+    let search = find_best_path(
+        &route[window.clone()],
+        break_at,
+        legs,
+        constraints,
+        model,
+        budget,
+        strategy,
+        started,
+        &mut progress,
+    );
+    if search.result.path.is_empty() {
+        return Err(match search.status {
+            Status::Complete => ScoreError::NoValidSolution,
+            Status::BudgetExhausted => ScoreError::BudgetExhausted,
+            Status::Cancelled => ScoreError::Cancelled,
+        });
+    }
+    // map the path back to the indices of the whole route and calculate the final distance
+    let path = search
+        .result
+        .path
+        .iter()
+        .map(|index| index + window.start)
+        .collect();
+    Ok(AnytimeResult {
+        result: OptimizationResult::new(path, route, model)?,
+        upper_bound: search.upper_bound,
+        status: search.status,
+        dropped: search.dropped,
+        cache: search.cache,
+    })
+}
+
+// Find the best valid path, which is empty if no path complies with the constraints.
+// The distance of the result is only approximated with the flat projection.
+//
+// The fixes which can not change the optimal distance are removed before the search,
+// the path of the result refers to the indices of the whole route.
+// With a coarse step, the coarse-to-fine result is the initial lower bound of the search.
+#[allow(clippy::too_many_arguments)]
+fn find_best_path<F: Float, T: Point, M: DistanceModel, P: FnMut(&Progress<F>) -> bool>(
+    route: &[T],
+    break_at: F,
+    legs: usize,
+    constraints: Constraints,
+    model: &M,
+    budget: Budget,
+    strategy: Strategy,
+    started: Instant,
+    progress: &mut P,
+) -> AnytimeResult<F> {
     let flat_points = to_flat_points(route);
-    let dist_matrix = half_dist_matrix(&flat_points);
+    let reduction = reduce(route, constraints);
+    let reduced_route: Vec<&T> = reduction.kept.iter().map(|&index| &route[index]).collect();
+    let reduced_points: Vec<FlatPoint<F>> = reduction
+        .kept
+        .iter()
+        .map(|&index| flat_points[index])
+        .collect();
+
+    let seed = strategy.coarse.and_then(|step| {
+        coarse_to_fine(
+            &reduced_route,
+            &reduced_points,
+            legs,
+            constraints,
+            model,
+            budget,
+            started,
+            step,
+            strategy.batch,
+        )
+    });
+    let mut search = search(
+        &reduced_route,
+        &reduced_points,
+        break_at,
+        legs,
+        constraints,
+        model,
+        budget,
+        started,
+        seed,
+        strategy.batch,
+        progress,
+    );
+    search.result.path = reduction.map_path(&search.result.path);
+    search.dropped = reduction.dropped;
+    search
+}
+
+// Solve the route downsampled to every step-th fix, then solve it again on the fixes within
+// step of the coarse turnpoints. Both searches run on subsets of the route, so the refined
+// path is a valid path of the route. Return None if no coarse path is valid.
+#[allow(clippy::too_many_arguments)]
+fn coarse_to_fine<F: Float, T: Point, M: DistanceModel>(
+    route: &[T],
+    flat_points: &[FlatPoint<F>],
+    legs: usize,
+    constraints: Constraints,
+    model: &M,
+    budget: Budget,
+    started: Instant,
+    step: usize,
+    batch: usize,
+) -> Option<OptimizationResult<F>> {
+    let solve = |subset: &[usize]| {
+        let sub_route: Vec<&T> = subset.iter().map(|&index| &route[index]).collect();
+        let sub_points: Vec<FlatPoint<F>> =
+            subset.iter().map(|&index| flat_points[index]).collect();
+        let result = search(
+            &sub_route,
+            &sub_points,
+            F::zero(),
+            legs,
+            constraints,
+            model,
+            budget,
+            started,
+            None,
+            batch,
+            &mut |_| true,
+        )
+        .result;
+        let path: Path = result.path.iter().map(|&index| subset[index]).collect();
+        let distance = flat_points.cum_distance(&path);
+        (!path.is_empty() && route.valid(path[0], path[path.len() - 1], constraints, distance))
+            .then_some(OptimizationResult {
+                path,
+                distance,
+                legs: Vec::new(),
+            })
+    };
+
+    let downsampled: Vec<usize> = (0..route.len()).step_by(step.max(1)).collect();
+    let coarse = solve(&downsampled)?;
+    let mut neighbourhood: Vec<usize> = coarse
+        .path
+        .iter()
+        .flat_map(|&index| index.saturating_sub(step)..(index + step + 1).min(route.len()))
+        .collect();
+    neighbourhood.sort_unstable();
+    neighbourhood.dedup();
+    solve(&neighbourhood)
+        .filter(|fine| fine.distance > coarse.distance)
+        .or(Some(coarse))
+}
+
+// The search of find_best_path on a route with its flat points, starting from the seed if it
+// is better than the best valid path of the first graph.
+//
+// The start candidates are evaluated by decreasing distance, so if the budget runs out,
+// the distance of the next candidate is an upper bound for all paths which have not been checked.
+// The graphs of up to batch candidates are built in parallel.
+#[allow(clippy::too_many_arguments)]
+fn search<F: Float, T: Point, M: DistanceModel, P: FnMut(&Progress<F>) -> bool>(
+    route: &[T],
+    flat_points: &[FlatPoint<F>],
+    break_at: F,
+    legs: usize,
+    constraints: Constraints,
+    model: &M,
+    budget: Budget,
+    started: Instant,
+    seed: Option<OptimizationResult<F>>,
+    batch: usize,
+    progress: &mut P,
+) -> AnytimeResult<F> {
+    let excluded: Vec<bool> = route
+        .iter()
+        .map(|point| !constraints.engine.allows_turnpoint(point))
+        .collect();
+    let dist_matrix = Distances::new(flat_points, &excluded, budget.memory);
 
     let graph = Graph::from_distance_matrix(&dist_matrix, legs);
-    let mut best_valid = graph.find_best_valid_solution(route);
+    // start without a solution if none of the paths in the graph complies with the constraints
+    let mut best_valid = graph
+        .find_best_valid_solution(route, constraints)
+        .unwrap_or(OptimizationResult {
+            path: Vec::new(),
+            distance: F::neg_infinity(),
+            legs: Vec::new(),
+        });
+    if let Some(seed) = seed.filter(|seed| seed.distance > best_valid.distance) {
+        best_valid = seed;
+    }
 
     let mut start_candidates = graph.get_start_candidates(best_valid.distance);
     if start_candidates.is_empty() {
-        return Some(OptimizationResult::new(best_valid.path, route));
+        return completed(best_valid);
     }
 
     let start_window = Bound::from(start_candidates.as_ref());
-    if let Some(improved) = best_valid.optimize_by_sliding(route, &flat_points, &start_window) {
+    if let Some(improved) =
+        best_valid.optimize_by_sliding(route, flat_points, &start_window, constraints, model)
+    {
         if improved.distance > best_valid.distance {
             best_valid = improved;
         }
     }
 
-    // for edge cases, sliding over the best invalid solution produces a valid one
+    // for edge cases, sliding over the best invalid solution produces a valid one,
+    // unless one of its turnpoints is excluded
     let best_invalid = graph.find_best_solution(route);
-    if let Some(improved) = best_invalid.optimize_by_sliding(route, &flat_points, &start_window) {
-        if improved.distance > best_valid.distance {
-            best_valid = improved;
+    if best_invalid.distance > F::neg_infinity() {
+        if let Some(improved) =
+            best_invalid.optimize_by_sliding(route, flat_points, &start_window, constraints, model)
+        {
+            if improved.distance > best_valid.distance {
+                best_valid = improved;
+            }
         }
     }
 
-    let minimum_stop = find_minimum_stop(&dist_matrix, best_valid.distance);
+    let minimum_stop = match find_minimum_stop(flat_points, best_valid.distance) {
+        Some(minimum_stop) => minimum_stop,
+        None => return completed(best_valid),
+    };
     let mut cache = Cache::new();
 
     start_candidates.retain(|c| c.distance > best_valid.distance);
 
-    while let Some(candidate) = start_candidates.pop() {
-        if candidate.distance < break_at {
-            return Some(best_valid);
+    let mut state = Progress {
+        remaining: start_candidates.len(),
+        best_distance: best_valid.distance,
+        checked: 0,
+        pruned: 0,
+        built: 0,
+        cache: CacheStats::default(),
+    };
+    loop {
+        // pop the next candidates which the cache can not rule out, stop before the first
+        // candidate below break_at or when the budget runs out
+        let mut pending = Vec::with_capacity(batch);
+        let mut stopped = None;
+        while pending.len() < batch {
+            let Some(candidate) = start_candidates.pop() else {
+                break;
+            };
+            state.remaining = start_candidates.len() + 1;
+            state.best_distance = best_valid.distance;
+            state.cache = cache.stats();
+            let status = if candidate.distance < break_at {
+                Some(Status::Complete)
+            } else if budget.exhausted(started, state.checked) {
+                Some(Status::BudgetExhausted)
+            } else if !progress(&state) {
+                Some(Status::Cancelled)
+            } else {
+                None
+            };
+            if let Some(status) = status {
+                stopped = Some((status, candidate.distance));
+                break;
+            }
+            state.checked += 1;
+            let stops = candidate.get_valid_stops(route, minimum_stop, constraints);
+            if stops.is_empty() {
+                continue;
+            }
+            let mut to_check = CacheItem::from_candidate(&candidate, stops);
+            if cache.check(&mut to_check, flat_points, best_valid.distance) {
+                // there is no need to add this to the cache, because the relation is transitive
+                // if A provides an upperbound for B, and B provides an upperbound for a later C
+                // then A provides an upperbound for C, so we don't need to add B to the cache
+                //
+                // BUT: adding this to the cache provides a speed-up on the test suite
+                cache.set(to_check);
+                state.pruned += 1;
+                continue;
+            }
+            pending.push((candidate, to_check));
         }
-        let stops = candidate.get_valid_stops(route, minimum_stop);
-        if stops.is_empty() {
-            continue;
-        }
-        let mut to_check = CacheItem::from_candidate(&candidate, stops);
-        if cache.check(&mut to_check, &flat_points, best_valid.distance) {
-            // there is no need to add this to the cache, because the relation is transitive
-            // if A provides an upperbound for B, and B provides an upperbound for a later C
-            // then A provides an upperbound for C, so we don't need to add B to the cache
-            //
-            // BUT: adding this to the cache provides a speed-up on the test suite
-            cache.set(to_check);
-            continue;
+        if pending.is_empty() && stopped.is_none() {
+            break;
         }
+        state.built += pending.len();
 
-        // do the full (expensive) optimization
-        let candidate_graph = Graph::for_candidate(&candidate, &dist_matrix, route, legs);
-        let best_valid_for_candidate = candidate_graph.find_best_valid_solution(route);
+        // do the full (expensive) optimizations, all of them against the best distance before the batch
+        let best_distance = best_valid.distance;
+        let results: Vec<_> = opt_par_iter(&pending)
+            .map(|(candidate, _)| {
+                optimize_candidate(
+                    candidate,
+                    &dist_matrix,
+                    route,
+                    legs,
+                    constraints,
+                    best_distance,
+                )
+            })
+            .collect();
 
-        to_check.distance = best_valid_for_candidate.distance;
-        cache.set(to_check);
+        // merge in the order of the candidates, like the sequential search
+        for ((_, mut to_check), (best_valid_for_candidate, max_distance)) in
+            pending.into_iter().zip(results)
+        {
+            to_check.distance = max_distance;
+            cache.set(to_check);
 
-        if best_valid_for_candidate.distance > best_valid.distance {
-            best_valid = best_valid_for_candidate;
-            start_candidates.retain(|it| it.distance > best_valid.distance);
+            if let Some(best_valid_for_candidate) = best_valid_for_candidate {
+                if best_valid_for_candidate.distance > best_valid.distance {
+                    best_valid = best_valid_for_candidate;
+                }
+            }
+        }
+        start_candidates.retain(|it| it.distance > best_valid.distance);
+
+        if let Some((status, distance)) = stopped {
+            return AnytimeResult {
+                upper_bound: distance.max(best_valid.distance),
+                result: best_valid,
+                status,
+                dropped: 0,
+                cache: cache.stats(),
+            };
         }
     }
 
-    Some(OptimizationResult::new(best_valid.path, route))
+    AnytimeResult {
+        cache: cache.stats(),
+        ..completed(best_valid)
+    }
 }
 
-// Calculate the cumulative distance when going from fix to fix. This places an upper limit on the
-// distance achievable with n legs and is used to calculate a minimum index where a path needs to end
-// to have the possibility to achieve a better result than distance
-fn find_minimum_stop(dist_matrix: &[Vec<f32>], distance: f32) -> usize {
-    let mut i = 0;
-    let mut sum = 0.0;
+fn completed<F: Float>(best_valid: OptimizationResult<F>) -> AnytimeResult<F> {
+    AnytimeResult {
+        upper_bound: best_valid.distance,
+        result: best_valid,
+        status: Status::Complete,
+        dropped: 0,
+        cache: CacheStats::default(),
+    }
+}
+
+// Find the best valid solution for a start candidate. Return it together with the maximum distance of any route
+// from the start of the candidate to a finish that is not ruled out by the candidate distance, which is needed to
+// keep the upper bounds of the cache correct.
+//
+// If the allowed altitude loss depends on the distance, the best route from the candidate might not comply with the
+// constraints although its finish has not been penalized. In this case, no valid route from the candidate is longer than
+// this route, so the graph is rebuild with its distance (which penalizes its finish) until the best route is valid
+// or can not beat the current best distance.
+fn optimize_candidate<F: Float, T: Point, D: DistanceMatrix<F>>(
+    candidate: &StartCandidate<F>,
+    dist_matrix: &D,
+    route: &[T],
+    legs: usize,
+    constraints: Constraints,
+    best_distance: F,
+) -> (Option<OptimizationResult<F>>, F) {
+    let mut max_distance = candidate.distance;
+    let mut relaxed_distance = None;
     loop {
-        sum += dist_matrix[i][1];
-        if sum > distance {
-            return i;
-        };
-        i += 1;
+        let graph = Graph::for_candidate(
+            candidate,
+            dist_matrix,
+            route,
+            legs,
+            constraints,
+            max_distance,
+        );
+        let best_valid = graph.find_best_valid_solution(route, constraints);
+        let best_valid_distance = best_valid
+            .as_ref()
+            .map_or(F::neg_infinity(), |result| result.distance);
+        let from_start = graph.find_solution_from(route, candidate.start);
+        let relaxed_distance = *relaxed_distance.get_or_insert(from_start.distance);
+
+        let stop = from_start.path[from_start.path.len() - 1];
+        if from_start.distance <= best_distance.max(best_valid_distance)
+            || from_start.distance >= max_distance
+            || route.valid(candidate.start, stop, constraints, from_start.distance)
+        {
+            return (best_valid, relaxed_distance.max(best_valid_distance));
+        }
+        max_distance = from_start.distance;
     }
 }
 
-// Generate a triangular matrix with the distances in kilometers between all points.
-// For each point, the distance to the following points is saved. This only allows
-// calculation of backward min-marginals
-pub fn half_dist_matrix(flat_points: &[FlatPoint<f32>]) -> Vec<Vec<f32>> {
-    opt_par_iter(flat_points)
-        .enumerate()
-        .map(|(i, p1)| flat_points[i..].iter().map(|p2| p1.distance(p2)).collect())
-        .collect()
+// Calculate the cumulative distance when going from fix to fix. This places an upper limit on the
+// distance achievable with n legs and is used to calculate a minimum index where a path needs to end
+// to have the possibility to achieve a better result than distance.
+// Return None if even the whole route is not longer than distance
+fn find_minimum_stop<F: Float>(flat_points: &[FlatPoint<F>], distance: F) -> Option<usize> {
+    let mut sum = F::zero();
+    flat_points
+        .windows(2)
+        .map(|points| points[0].distance(&points[1]))
+        .position(|leg| {
+            sum += leg;
+            sum > distance
+        })
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::distance::{FaiSphere, Vincenty};
+    use crate::error::ScoreError;
+    use crate::flat::to_flat_points;
+    use crate::flight::{AltitudePolicy, Flight};
     use crate::free;
-    use crate::free::OptimizationResult;
-    use crate::point::PointImpl;
+    use crate::free::{Budget, OptimizationResult, Progress};
+    use crate::point::{AltitudeRule, ApproxDistance, Constraints, EngineRule, PointImpl, Valid};
+    use crate::result::Status;
     use assert_approx_eq::assert_approx_eq;
-    use igc::records::BRecord;
+    use flat_projection::FlatPoint;
     use igc::util::Time;
+    use std::time::Duration;
 
     const LEGS: usize = 6;
 
@@ -117,7 +630,26 @@ mod tests {
         let release = Time::from_hms(8, 12, 29);
         let result = run_free_test(include_str!("../fixtures/2023-06-17_288167.igc"), release);
         assert_approx_eq!(result.distance, 1018.5, 0.1);
-        assert_eq!(result.path, [0, 936, 2847, 3879, 5048, 7050, 8128]);
+        assert_eq!(result.path, [131, 1067, 2978, 4010, 5179, 7181, 8259]);
+    }
+
+    #[test]
+    fn free_distance_in_double_precision() {
+        let flight = Flight::parse(
+            include_str!("../fixtures/2023-06-17_288167.igc"),
+            AltitudePolicy::Pressure,
+        )
+        .unwrap();
+        let constraints = Constraints {
+            start_time: Some(Time::from_hms(8, 12, 29).seconds_since_midnight().into()),
+            ..Default::default()
+        };
+        let preview = free::optimize(&flight.fixes, 0.0_f32, LEGS, constraints, &Vincenty).unwrap();
+        let exact = free::optimize(&flight.fixes, 0.0_f64, LEGS, constraints, &Vincenty).unwrap();
+        assert_eq!(exact.path, preview.path);
+        assert_approx_eq!(exact.distance, 1018.542, 0.001);
+        // the single precision result is off by about a meter
+        assert_approx_eq!(exact.distance, f64::from(preview.distance), 0.01);
     }
 
     #[test]
@@ -125,31 +657,503 @@ mod tests {
         let release = Time::from_hms(8, 16, 30);
         let result = run_free_test(include_str!("../fixtures/schunk_1000m.igc"), release);
         assert_approx_eq!(result.distance, 1158.61, 0.1);
-        assert_eq!(result.path, [335, 10099, 14740, 15482, 24198, 34160, 35798]);
+        assert_eq!(
+            result.path,
+            [1505, 11269, 15910, 16652, 25368, 35330, 36968]
+        );
+    }
+
+    #[test]
+    fn free_distance_matches_brute_force() {
+        let route = random_route(24);
+        let flat_points = to_flat_points(&route);
+        for constraints in [
+            AltitudeRule::Fixed(300).into(),
+            AltitudeRule::Relative(0.02).into(),
+            AltitudeRule::Disabled.into(),
+            Constraints {
+                max_duration: Some(600),
+                ..Default::default()
+            },
+            Constraints {
+                engine: EngineRule::NoTurnpoints { threshold: 500 },
+                ..Default::default()
+            },
+        ] {
+            let result = free::optimize(&route, 0.0, 3, constraints, &Vincenty).unwrap();
+            assert!(route.valid(
+                result.path[0],
+                result.path[3],
+                constraints,
+                flat_points.cum_distance(&result.path)
+            ));
+            assert_approx_eq!(
+                flat_points.cum_distance(&result.path),
+                brute_force(&route, &flat_points, 3, constraints),
+                1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn distance_model_measures_the_result() {
+        let route = random_route(24);
+        let vincenty =
+            free::optimize(&route, 0.0_f32, 3, AltitudeRule::Disabled, &Vincenty).unwrap();
+        let sphere = free::optimize(&route, 0.0, 3, AltitudeRule::Disabled, &FaiSphere).unwrap();
+        assert_eq!(sphere.path, vincenty.path);
+        let measured = OptimizationResult::new(vincenty.path, &route, &FaiSphere).unwrap();
+        assert_eq!(sphere.distance, measured.distance);
+        assert_eq!(sphere.legs, measured.legs);
+        assert!((sphere.distance - vincenty.distance).abs() > 1e-3);
+    }
+
+    #[test]
+    fn reduced_track_matches_brute_force() {
+        // every third fix is logged twice, a fix halfway to the next follows every fourth fix
+        let fixes = random_route(24);
+        let route: Vec<PointImpl> = fixes
+            .iter()
+            .zip(fixes.iter().skip(1).map(Some).chain([None]))
+            .enumerate()
+            .flat_map(|(i, (point, next))| {
+                let mut points = vec![point.clone(); if i % 3 == 0 { 2 } else { 1 }];
+                if let Some(next) = next.filter(|_| i % 4 == 1) {
+                    points.push(PointImpl {
+                        latitude: (point.latitude + next.latitude) / 2.0,
+                        longitude: (point.longitude + next.longitude) / 2.0,
+                        ..point.clone()
+                    });
+                }
+                points
+            })
+            .collect();
+        let flat_points = to_flat_points(&route);
+        for (constraints, dropped) in [
+            (AltitudeRule::Fixed(300).into(), 8),
+            (
+                Constraints {
+                    max_duration: Some(600),
+                    ..Default::default()
+                },
+                8,
+            ),
+            (
+                Constraints {
+                    engine: EngineRule::NoTurnpoints { threshold: 500 },
+                    ..Default::default()
+                },
+                8,
+            ),
+            (AltitudeRule::Disabled.into(), 14),
+        ] {
+            let anytime =
+                free::optimize_within(&route, 0.0, 3, constraints, &Vincenty, Budget::default())
+                    .unwrap();
+            assert_eq!(anytime.dropped, dropped);
+            assert_approx_eq!(
+                flat_points.cum_distance(&anytime.result.path),
+                brute_force(&route, &flat_points, 3, constraints),
+                1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn coarse_to_fine_matches_brute_force() {
+        let route = random_route(60);
+        let flat_points = to_flat_points(&route);
+        for constraints in [
+            AltitudeRule::Fixed(300).into(),
+            AltitudeRule::Relative(0.02).into(),
+            Constraints {
+                engine: EngineRule::NoTurnpoints { threshold: 500 },
+                ..Default::default()
+            },
+        ] {
+            for step in [1, 4, 100] {
+                let anytime = free::optimize_coarse_to_fine(
+                    &route,
+                    0.0,
+                    3,
+                    constraints,
+                    &Vincenty,
+                    Budget::default(),
+                    step,
+                )
+                .unwrap();
+                assert_eq!(anytime.status, Status::Complete);
+                assert_approx_eq!(
+                    flat_points.cum_distance(&anytime.result.path),
+                    brute_force(&route, &flat_points, 3, constraints),
+                    1e-3
+                );
+            }
+        }
+
+        let flight = Flight::parse(
+            include_str!("../fixtures/2023-06-17_288167.igc"),
+            AltitudePolicy::Pressure,
+        )
+        .unwrap();
+        let constraints = Constraints {
+            start_time: Some(Time::from_hms(8, 12, 29).seconds_since_midnight().into()),
+            ..Default::default()
+        };
+        let anytime = free::optimize_coarse_to_fine(
+            &flight.fixes,
+            0.0_f32,
+            LEGS,
+            constraints,
+            &Vincenty,
+            Budget::default(),
+            10,
+        )
+        .unwrap();
+        assert_eq!(
+            anytime.result.path,
+            [131, 1067, 2978, 4010, 5179, 7181, 8259]
+        );
+    }
+
+    #[test]
+    fn parallel_candidates_match_sequential_search() {
+        let route = random_route(60);
+        for constraints in [
+            AltitudeRule::Fixed(300).into(),
+            AltitudeRule::Relative(0.02).into(),
+            Constraints {
+                max_duration: Some(1800),
+                ..Default::default()
+            },
+        ] {
+            let sequential: OptimizationResult =
+                free::optimize(&route, 0.0, 3, constraints, &Vincenty).unwrap();
+            for batch in [1, 3, 16] {
+                let parallel = free::optimize_in_parallel(
+                    &route,
+                    0.0,
+                    3,
+                    constraints,
+                    &Vincenty,
+                    Budget::default(),
+                    batch,
+                )
+                .unwrap();
+                assert_eq!(parallel.status, Status::Complete);
+                assert_eq!(parallel.result.path, sequential.path);
+                assert_eq!(parallel.result.distance, sequential.distance);
+            }
+        }
+
+        let release = Time::from_hms(8, 12, 29);
+        let flight = Flight::parse(
+            include_str!("../fixtures/2023-06-17_288167.igc"),
+            AltitudePolicy::Pressure,
+        )
+        .unwrap();
+        let constraints = Constraints {
+            start_time: Some(release.seconds_since_midnight().into()),
+            ..Default::default()
+        };
+        let parallel = free::optimize_in_parallel(
+            &flight.fixes,
+            0.0_f32,
+            LEGS,
+            constraints,
+            &Vincenty,
+            Budget::default(),
+            8,
+        )
+        .unwrap();
+        assert_eq!(
+            parallel.result.path,
+            [131, 1067, 2978, 4010, 5179, 7181, 8259]
+        );
+    }
+
+    #[test]
+    fn distances_on_the_fly_give_identical_results() {
+        let on_the_fly = Budget {
+            memory: Some(0),
+            ..Default::default()
+        };
+        let route = random_route(60);
+        for constraints in [
+            AltitudeRule::Fixed(300).into(),
+            Constraints {
+                engine: EngineRule::NoTurnpoints { threshold: 500 },
+                ..Default::default()
+            },
+        ] {
+            let stored = free::optimize(&route, 0.0_f32, 3, constraints, &Vincenty).unwrap();
+            let result =
+                free::optimize_within(&route, 0.0_f32, 3, constraints, &Vincenty, on_the_fly)
+                    .unwrap()
+                    .result;
+            assert_eq!(result.path, stored.path);
+            assert_eq!(result.distance, stored.distance);
+        }
+
+        let flight = Flight::parse(
+            include_str!("../fixtures/2023-06-17_288167.igc"),
+            AltitudePolicy::Pressure,
+        )
+        .unwrap();
+        let constraints = Constraints {
+            start_time: Some(Time::from_hms(8, 12, 29).seconds_since_midnight().into()),
+            ..Default::default()
+        };
+        let result = free::optimize_within(
+            &flight.fixes,
+            0.0_f32,
+            LEGS,
+            constraints,
+            &Vincenty,
+            on_the_fly,
+        )
+        .unwrap()
+        .result;
+        assert_eq!(result.path, [131, 1067, 2978, 4010, 5179, 7181, 8259]);
+    }
+
+    #[test]
+    fn budget_returns_best_so_far_with_upper_bound() {
+        let route = random_route(60);
+        let flat_points = to_flat_points(&route);
+        let constraints = AltitudeRule::Fixed(300).into();
+        let optimum = brute_force(&route, &flat_points, 3, constraints);
+
+        let stopped = Budget {
+            candidates: Some(0),
+            ..Default::default()
+        };
+        let anytime =
+            free::optimize_within(&route, 0.0, 3, constraints, &Vincenty, stopped).unwrap();
+        assert_eq!(anytime.status, Status::BudgetExhausted);
+        assert!(route.valid(
+            anytime.result.path[0],
+            anytime.result.path[3],
+            constraints,
+            0.0
+        ));
+        assert!(flat_points.cum_distance(&anytime.result.path) <= optimum);
+        assert!(anytime.upper_bound >= optimum);
+
+        let anytime =
+            free::optimize_within(&route, 0.0, 3, constraints, &Vincenty, Budget::default())
+                .unwrap();
+        assert_eq!(anytime.status, Status::Complete);
+        assert_approx_eq!(anytime.upper_bound, optimum, 1e-3);
+        let unlimited = Budget {
+            time: Some(Duration::from_secs(3600)),
+            candidates: Some(1000),
+            memory: None,
+        };
+        let result =
+            free::optimize_within(&route, 0.0, 3, constraints, &Vincenty, unlimited).unwrap();
+        assert_eq!(result.status, Status::Complete);
+        assert_eq!(result.result.path, anytime.result.path);
+    }
+
+    #[test]
+    fn progress_reports_candidates_and_cancels() {
+        let route = random_route(60);
+        let constraints = AltitudeRule::Fixed(300);
+        let mut reports: Vec<Progress<f32>> = Vec::new();
+        let result = free::optimize_with_progress(
+            &route,
+            0.0,
+            3,
+            constraints,
+            &Vincenty,
+            Budget::default(),
+            |progress| {
+                reports.push(*progress);
+                true
+            },
+        )
+        .unwrap();
+        assert_eq!(result.status, Status::Complete);
+        assert!(reports.len() > 1);
+        for (checked, progress) in reports.iter().enumerate() {
+            assert_eq!(progress.checked, checked);
+            assert!(progress.pruned + progress.built <= progress.checked);
+            assert!(progress.remaining > 0);
+        }
+        assert!(reports
+            .windows(2)
+            .all(|pair| pair[1].remaining < pair[0].remaining));
+
+        let cancelled = free::optimize_with_progress(
+            &route,
+            0.0_f32,
+            3,
+            constraints,
+            &Vincenty,
+            Budget::default(),
+            |progress| progress.checked < 1,
+        )
+        .unwrap();
+        assert_eq!(cancelled.status, Status::Cancelled);
+        assert!(cancelled.upper_bound >= reports[1].best_distance);
+    }
+
+    #[test]
+    fn free_distance_with_relative_rule() {
+        let release = Time::from_hms(8, 12, 29);
+        let result = run_free_test_with_rule(
+            include_str!("../fixtures/2023-06-17_288167.igc"),
+            release,
+            AltitudeRule::Relative(0.0002),
+        );
+        assert_approx_eq!(result.distance, 1015.53, 0.1);
+        assert_eq!(result.path, [152, 1067, 2978, 4010, 5179, 7181, 8254]);
+    }
+
+    // deterministic pseudo random route with a climb in the first half and a descent in the second half
+    fn random_route(n: usize) -> Vec<PointImpl> {
+        let mut seed: u32 = 7;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as f64 / 65_536.0
+        };
+        (0..n)
+            .map(|i| PointImpl {
+                latitude: 50.0 + next() - 0.5,
+                longitude: 10.0 + next() - 0.5,
+                altitude: if i < n / 2 {
+                    100 * i as i16
+                } else {
+                    100 * (n - i) as i16
+                },
+                timestamp: Some(60 * i as i64),
+                engine_noise: Some(if i % 5 == 4 { 800 } else { 50 }),
+            })
+            .collect()
+    }
+
+    // check all paths with legs + 1 turnpoints
+    fn brute_force(
+        route: &[PointImpl],
+        flat_points: &[FlatPoint<f32>],
+        legs: usize,
+        constraints: Constraints,
+    ) -> f32 {
+        let mut best = 0.0_f32;
+        let mut path = vec![0; legs + 1];
+        loop {
+            let distance = flat_points.cum_distance(&path);
+            if route.valid(path[0], path[legs], constraints, distance)
+                && path
+                    .iter()
+                    .all(|&index| constraints.engine.allows_turnpoint(&route[index]))
+            {
+                best = best.max(distance);
+            }
+            // advance to the next non-decreasing path
+            let Some(position) = (0..=legs).rev().find(|&i| path[i] + 1 < route.len()) else {
+                return best;
+            };
+            let value = path[position] + 1;
+            path[position..].iter_mut().for_each(|index| *index = value);
+        }
+    }
+
+    #[test]
+    fn invalid_routes_return_errors() {
+        let route = random_route(5);
+        assert_eq!(
+            free::optimize(&route[..0], 0.0, LEGS, AltitudeRule::default(), &Vincenty).unwrap_err(),
+            ScoreError::EmptyRoute
+        );
+        assert_eq!(
+            free::optimize(&route, 0.0, LEGS, AltitudeRule::default(), &Vincenty).unwrap_err(),
+            ScoreError::TooFewPoints {
+                points: 5,
+                required: LEGS + 1
+            }
+        );
+        assert_eq!(
+            free::optimize(&route, 0.0, 2, AltitudeRule::Fixed(-10_000), &Vincenty).unwrap_err(),
+            ScoreError::NoValidSolution
+        );
+    }
+
+    #[test]
+    fn time_window_restricts_route() {
+        let route = random_route(24);
+        let constraints = Constraints {
+            start_time: Some(300),
+            end_time: Some(900),
+            ..Default::default()
+        };
+        let result = free::optimize(&route, 0.0, 3, constraints, &Vincenty).unwrap();
+        assert!(result.path.iter().all(|&index| (5..=15).contains(&index)));
+        let window =
+            free::optimize(&route[5..=15], 0.0, 3, AltitudeRule::default(), &Vincenty).unwrap();
+        let path: Vec<_> = window.path.iter().map(|index| index + 5).collect();
+        assert_eq!(result.path, path);
+        assert_eq!(result.distance, window.distance);
+
+        let mut route = route;
+        route[3].timestamp = None;
+        assert_eq!(
+            free::optimize(&route, 0.0, 3, constraints, &Vincenty).unwrap_err(),
+            ScoreError::MissingTimestamp { index: 3 }
+        );
+    }
+
+    #[test]
+    fn scoring_restarts_after_last_engine_run() {
+        let mut route = random_route(24);
+        route.iter_mut().for_each(|point| point.engine_noise = None);
+        route[8].engine_noise = Some(900);
+        let constraints = Constraints {
+            engine: EngineRule::AfterLastRun { threshold: 500 },
+            ..Default::default()
+        };
+        let result = free::optimize(&route, 0.0, 3, constraints, &Vincenty).unwrap();
+        let after =
+            free::optimize(&route[9..], 0.0, 3, AltitudeRule::default(), &Vincenty).unwrap();
+        let path: Vec<_> = after.path.iter().map(|index| index + 9).collect();
+        assert_eq!(result.path, path);
+    }
+
+    #[test]
+    fn identical_points_have_zero_distance() {
+        let route = vec![
+            PointImpl {
+                latitude: 50.0,
+                longitude: 10.0,
+                altitude: 0,
+                timestamp: None,
+                engine_noise: None,
+            };
+            10
+        ];
+        let result = free::optimize(&route, 0.0, 3, AltitudeRule::default(), &Vincenty).unwrap();
+        assert_eq!(result.distance, 0.0);
     }
 
     fn run_free_test(file: &str, release: Time) -> OptimizationResult {
+        run_free_test_with_rule(file, release, AltitudeRule::default())
+    }
+
+    fn run_free_test_with_rule(
+        file: &str,
+        release: Time,
+        rule: AltitudeRule,
+    ) -> OptimizationResult {
         env_logger::try_init().ok();
 
-        let fixes = file
-            .lines()
-            .filter(|l| l.starts_with('B'))
-            .filter_map(|line| {
-                BRecord::parse(&line).ok().map_or(None, |record| {
-                    if record.timestamp.seconds_since_midnight() >= release.seconds_since_midnight()
-                    {
-                        Some(PointImpl {
-                            latitude: record.pos.lat.into(),
-                            longitude: record.pos.lon.into(),
-                            altitude: record.pressure_alt,
-                        })
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect::<Vec<_>>();
+        let flight = Flight::parse(file, AltitudePolicy::Pressure).unwrap();
 
-        free::optimize(&fixes, 0.0, LEGS).unwrap()
+        let constraints = Constraints {
+            altitude: rule,
+            start_time: Some(release.seconds_since_midnight().into()),
+            ..Default::default()
+        };
+        free::optimize(&flight.fixes, 0.0, LEGS, constraints, &Vincenty).unwrap()
     }
 }
@@ -0,0 +1,298 @@
+// Read flights from GPX files: every track point (trkpt) with its position, elevation and time
+// becomes a fix. All other elements, e.g. waypoints and routes, are ignored.
+//
+// This is not a complete XML parser. It reads the attributes of the trkpt tags, which may be
+// separated by any whitespace, and the ele and time elements inside of them.
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::error::GpxError;
+use crate::flight::{Fix, Flight, SECONDS_PER_DAY};
+use crate::point::to_altitude;
+
+// Read and parse a GPX file. Invalid UTF-8 is replaced.
+pub fn read<P: AsRef<std::path::Path>>(path: P) -> Result<Flight, GpxError> {
+    let content = fs::read(path)?;
+    parse(&String::from_utf8_lossy(&content))
+}
+
+// Parse the track points of a GPX file, which need a position and a time. The elevation is used
+// as GNSS altitude, there is no pressure altitude. Track points without an elevation get an
+// altitude of 0, so the altitude rule should be disabled for such tracks. Times with an offset
+// (e.g. +02:00) are converted to UTC. Errors are reported with the line of the track point.
+pub fn parse(content: &str) -> Result<Flight, GpxError> {
+    let mut fixes: Vec<Fix> = Vec::new();
+    let mut first_day = None;
+    let mut offset = 0;
+    // the line of the track point, only the content since the previous one is counted
+    let (mut line, mut last_offset) = (1, 0);
+    while let Some(start) = find_tag(&content[offset..], "trkpt") {
+        offset += start;
+        line += content[last_offset..offset].matches('\n').count();
+        last_offset = offset;
+        let malformed = |reason| GpxError::MalformedTrackPoint { line, reason };
+        let rest = &content[offset..];
+        let tag_end = rest.find('>').ok_or(malformed("syntax error"))?;
+        let tag = &rest[..tag_end];
+        // a self-closing track point has no elements
+        let (tag, body) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, ""),
+            None => {
+                let end = rest
+                    .find("</trkpt>")
+                    .ok_or(malformed("unclosed track point"))?;
+                (tag, &rest[tag_end + 1..end])
+            }
+        };
+        offset += tag_end + 1 + body.len();
+
+        let coordinate = |name| {
+            attribute(tag, name)
+                .and_then(|value| value.parse::<f64>().ok())
+                .ok_or(malformed("invalid position"))
+        };
+        let altitude = match element(body, "ele") {
+            Some(value) => {
+                let altitude = value
+                    .parse::<f64>()
+                    .map_err(|_| malformed("invalid elevation"))?;
+                to_altitude(altitude).ok_or(malformed("elevation out of range"))?
+            }
+            None => 0,
+        };
+        let (day, seconds) = parse_time(element(body, "time").ok_or(malformed("missing time"))?)
+            .ok_or(malformed("invalid time"))?;
+        let first_day = *first_day.get_or_insert(day);
+        fixes.push(Fix {
+            latitude: coordinate("lat")?,
+            longitude: coordinate("lon")?,
+            altitude,
+            pressure_altitude: 0,
+            gnss_altitude: altitude,
+            timestamp: (day - first_day) * SECONDS_PER_DAY + seconds,
+            valid: true,
+            enl: None,
+            mop: None,
+        });
+    }
+    Ok(Flight {
+        headers: HashMap::new(),
+        extensions: Vec::new(),
+        task: None,
+        fixes,
+    })
+}
+
+// Return the position of the next opening tag with the given name, but not of tags which only
+// start with the name (e.g. trkptExtension)
+fn find_tag(content: &str, name: &str) -> Option<usize> {
+    let pattern = format!("<{}", name);
+    let mut offset = 0;
+    while let Some(start) = content[offset..].find(&pattern) {
+        let position = offset + start;
+        let next = content[position + pattern.len()..].chars().next();
+        match next {
+            Some(c) if !(c.is_whitespace() || c == '>' || c == '/') => {}
+            _ => return Some(position),
+        }
+        offset = position + pattern.len();
+    }
+    None
+}
+
+// Return the value of an attribute of an XML tag (without the closing bracket). The attributes
+// may be separated by any whitespace, also around the equals sign.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    // skip the name of the tag
+    let mut rest = tag.trim_start_matches('<');
+    rest = rest.trim_start_matches(|c: char| !c.is_whitespace());
+    loop {
+        rest = rest.trim_start();
+        let (key, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value = &value[1..];
+        let end = value.find(quote)?;
+        if key.trim_end() == name {
+            return Some(&value[..end]);
+        }
+        rest = &value[end + 1..];
+    }
+}
+
+// Return the trimmed text of the first element with the given name
+fn element<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    let start = content.find(&format!("<{}>", name))? + name.len() + 2;
+    let stop = content[start..].find(&format!("</{}>", name))?;
+    Some(content[start..start + stop].trim())
+}
+
+// Parse an ISO 8601 time like 2023-06-17T08:12:29Z or 2023-06-17T10:12:29+02:00 (fractions of
+// seconds are ignored) into the days since 1970-01-01 and the seconds since midnight in UTC.
+// A time without an offset is read as UTC.
+fn parse_time(time: &str) -> Option<(i64, i64)> {
+    let (date, time) = time.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|value| value.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(index) => (&time[..index], parse_offset(&time[index..])?),
+        None => (time, 0),
+    };
+    let mut time = time.splitn(3, ':');
+    let hours: i64 = time.next()?.parse().ok()?;
+    let minutes: i64 = time.next()?.parse().ok()?;
+    let seconds: f64 = time.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 {
+        return None;
+    }
+    // days from the civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let utc = days * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds as i64 - offset;
+    Some((
+        utc.div_euclid(SECONDS_PER_DAY),
+        utc.rem_euclid(SECONDS_PER_DAY),
+    ))
+}
+
+// Parse the offset of a time to UTC (Z, +hh:mm, +hhmm or +hh) into seconds
+fn parse_offset(offset: &str) -> Option<i64> {
+    let sign = match offset.chars().next()? {
+        'Z' if offset.len() == 1 => return Some(0),
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = offset[1..].replace(':', "");
+    if !matches!(digits.len(), 2 | 4) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits
+        .get(2..)
+        .filter(|m| !m.is_empty())
+        .map_or(Some(0), |m| m.parse().ok())?;
+    (hours <= 23 && minutes <= 59).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn malformed(content: &str) -> Option<(usize, &'static str)> {
+        match parse(content) {
+            Err(GpxError::MalformedTrackPoint { line, reason }) => Some((line, reason)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_track_points() {
+        let content = r#"<?xml version="1.0"?>
+<gpx version="1.1"><trk><trkseg>
+  <trkpt lat="50.5" lon='10.25'><ele>1234.6</ele><time>2023-06-17T23:59:59Z</time></trkpt>
+  <trkpt lon="10.5" lat="50.75">
+    <ele>1200</ele>
+    <time>2023-06-18T00:00:01.500Z</time>
+  </trkpt>
+</trkseg></trk></gpx>"#;
+        let flight = parse(content).unwrap();
+        assert_eq!(flight.fixes.len(), 2);
+        let fix = &flight.fixes[0];
+        assert_eq!((fix.latitude, fix.longitude), (50.5, 10.25));
+        assert_eq!((fix.altitude, fix.gnss_altitude), (1235, 1235));
+        assert_eq!(fix.timestamp, SECONDS_PER_DAY - 1);
+        assert_eq!(flight.fixes[1].longitude, 10.5);
+        assert_eq!(flight.fixes[1].timestamp, SECONDS_PER_DAY + 1);
+    }
+
+    #[test]
+    fn malformed_track_points_are_errors() {
+        let content = "<gpx>\n<trkpt lat=\"50\" lon=\"10\"><ele>0</ele></trkpt>\n</gpx>";
+        assert_eq!(malformed(content), Some((2, "missing time")));
+        let content = "<gpx>\n<trkpt lat=\"50\" lon=\"10\"><ele>40000</ele></trkpt>\n</gpx>";
+        assert_eq!(malformed(content), Some((2, "elevation out of range")));
+        let content = "<gpx>\n<trkpt lat=\"50\"><time>2023-06-17T08:12:29Z</time></trkpt>";
+        assert_eq!(malformed(content), Some((2, "invalid position")));
+        assert_eq!(parse_time("2023-06-17T08:12:29Z"), Some((19_525, 29_549)));
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), Some((0, 0)));
+        assert_eq!(parse_time("2023-13-17T08:12:29Z"), None);
+        assert_eq!(parse_time("2023-06-17T08:12:29+25:00"), None);
+    }
+
+    #[test]
+    fn track_points_without_elevation_are_accepted() {
+        let content = r#"<trkpt lat="50" lon="10"><time>2023-06-17T08:12:29Z</time></trkpt>"#;
+        let flight = parse(content).unwrap();
+        assert_eq!(flight.fixes[0].altitude, 0);
+        assert_eq!(flight.fixes[0].timestamp, 29_549);
+    }
+
+    #[test]
+    fn time_offsets_are_converted_to_utc() {
+        assert_eq!(
+            parse_time("2023-06-17T10:12:29+02:00"),
+            Some((19_525, 29_549))
+        );
+        assert_eq!(
+            parse_time("2023-06-17T10:12:29+0200"),
+            Some((19_525, 29_549))
+        );
+        assert_eq!(
+            parse_time("2023-06-17T06:42:29.5-01:30"),
+            Some((19_525, 29_549))
+        );
+        // the offset moves the time to the previous day
+        assert_eq!(
+            parse_time("2023-06-18T01:00:00+02:00"),
+            Some((19_525, 23 * 3600))
+        );
+
+        let content = r#"
+<trkpt lat="50" lon="10"><time>2023-06-18T01:59:59+02:00</time></trkpt>
+<trkpt lat="50" lon="10"><time>2023-06-18T00:00:01Z</time></trkpt>"#;
+        let flight = parse(content).unwrap();
+        assert_eq!(flight.fixes[1].timestamp - flight.fixes[0].timestamp, 2);
+    }
+
+    #[test]
+    fn self_closing_track_points_are_read_on_their_own() {
+        // the self-closing point has no time, it is not merged with the following point
+        let content = r#"<gpx>
+<trkpt lat="50" lon="10"/>
+<trkpt lat="51" lon="11"><time>2023-06-17T08:12:29Z</time></trkpt>
+</gpx>"#;
+        assert_eq!(malformed(content), Some((2, "missing time")));
+
+        let content = r#"<gpx>
+<trkpt lat="51" lon="11"><time>2023-06-17T08:12:29Z</time></trkpt>
+<trkpt lat="52" lon="12" />
+<trkpt lat="53" lon="13"><time>2023-06-17T08:12:30Z</time></trkpt>"#;
+        assert_eq!(malformed(content), Some((3, "missing time")));
+    }
+
+    #[test]
+    fn attributes_may_be_separated_by_any_whitespace() {
+        let content =
+            "<trkpt\tlat=\"50.5\"\n  lon = '10.25'><time>2023-06-17T08:12:29Z</time></trkpt>";
+        let flight = parse(content).unwrap();
+        assert_eq!(
+            (flight.fixes[0].latitude, flight.fixes[0].longitude),
+            (50.5, 10.25)
+        );
+        // an attribute which only ends with the name is not the attribute
+        assert_eq!(attribute(r#"<trkpt xlat="1" lat="2""#, "lat"), Some("2"));
+        assert_eq!(attribute(r#"<trkpt xlat="1""#, "lat"), None);
+        assert_eq!(find_tag("<trkptExtension><trkpt>", "trkpt"), Some(16));
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        assert!(matches!(read("fixtures/missing.gpx"), Err(GpxError::Io(_))));
+    }
+}
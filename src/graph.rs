@@ -1,55 +1,60 @@
 use ord_subset::OrdVar;
 
+use crate::cache::StopSet;
+use crate::float::Float;
+use crate::matrix::DistanceMatrix;
 use crate::parallel::*;
-use crate::point::{Point, Valid};
-use std::collections::HashSet;
+use crate::point::{Constraints, Path, Point, Valid};
 
 use crate::result::OptimizationResult;
 
 #[derive(Debug)]
-pub struct StartCandidate {
-    pub distance: f32,
+pub struct StartCandidate<F> {
+    pub distance: F,
     pub start: usize,
 }
 
-impl StartCandidate {
-    pub fn new(distance: f32, start: usize) -> Self {
+impl<F: Float> StartCandidate<F> {
+    pub fn new(distance: F, start: usize) -> Self {
         StartCandidate { distance, start }
     }
 
     // Return all points that would be valid endpoints for a route with the StartCandidate
     // Also filter out endpoints that are below minimum_stop, as they can not beat the current best
-    pub fn get_valid_stops<T: Point>(&self, route: &[T], minimum_stop: usize) -> HashSet<usize> {
-        let start_altitude = route[self.start].altitude();
-        route
-            .iter()
-            .enumerate()
-            .skip(self.start)
-            .filter(|(index, cell)| {
-                *index > minimum_stop && start_altitude - cell.altitude() <= 1000
+    //
+    // The distance of the candidate is an upper bound for all routes from its start, so endpoints
+    // that do not comply with the constraints for this distance can not be part of a valid route
+    pub fn get_valid_stops<T: Point>(
+        &self,
+        route: &[T],
+        minimum_stop: usize,
+        constraints: Constraints,
+    ) -> StopSet {
+        (self.start..route.len())
+            .filter(|&index| {
+                index > minimum_stop && route.valid(self.start, index, constraints, self.distance)
             })
-            .map(|(index, _)| index)
             .collect()
     }
 }
 
-pub struct Graph {
-    g: Vec<Vec<GraphCell>>,
+pub struct Graph<F> {
+    g: Vec<Vec<GraphCell<F>>>,
 }
 
 #[derive(Debug)]
-struct GraphCell {
+struct GraphCell<F> {
     prev_index: usize,
-    distance: f32,
+    distance: F,
 }
 
 // A layered graph with size [n_turnpoints, n_gps_points]
 //
 // Cell at [i, j]: If GPS point j is selected as turnpoint number i, what is the distance I can achieve via the previous i-1 turnpoints?
 // By selecting the maximum distance cell in the last layer, the graph can be iterated to find the best path.
-impl Graph {
+impl<F: Float> Graph<F> {
     // Return the remaining candidates that have the option of being better than the current best
-    pub fn get_start_candidates(&self, current_best: f32) -> Vec<StartCandidate> {
+    pub fn get_start_candidates(&self, current_best: F) -> Vec<StartCandidate<F>> {
         let mut candidates: Vec<_> = self
             .g
             .last()
@@ -64,15 +69,17 @@ impl Graph {
     }
 
     // Build the graph without considering the 1000m rule
-    pub fn from_distance_matrix(dist_matrix: &[Vec<f32>], legs: usize) -> Self {
-        let mut graph: Vec<Vec<GraphCell>> = Vec::with_capacity(legs);
-        let layer: Vec<GraphCell> = opt_par_iter(dist_matrix)
-            .enumerate()
-            .map(|(tp_index, distances)| {
-                distances
-                    .iter()
+    pub fn from_distance_matrix<D: DistanceMatrix<F> + ?Sized>(
+        dist_matrix: &D,
+        legs: usize,
+    ) -> Self {
+        let mut graph: Vec<Vec<GraphCell<F>>> = Vec::with_capacity(legs);
+        let layer: Vec<GraphCell<F>> = opt_par_range(0..dist_matrix.points())
+            .map(|tp_index| {
+                dist_matrix
+                    .row(tp_index)
                     .enumerate()
-                    .map(|(start, &distance)| GraphCell {
+                    .map(|(start, distance)| GraphCell {
                         prev_index: start + tp_index,
                         distance,
                     })
@@ -86,14 +93,13 @@ impl Graph {
         for layer_index in 1..legs {
             let last_layer = &graph[layer_index - 1];
 
-            let layer: Vec<GraphCell> = opt_par_iter(dist_matrix)
-                .enumerate()
-                .map(|(tp_index, distances)| {
-                    distances
-                        .iter()
+            let layer: Vec<GraphCell<F>> = opt_par_range(0..dist_matrix.points())
+                .map(|tp_index| {
+                    dist_matrix
+                        .row(tp_index)
                         .zip(last_layer.iter().skip(tp_index))
                         .enumerate()
-                        .map(|(prev_index, (&leg_dist, last_layer_cell))| {
+                        .map(|(prev_index, (leg_dist, last_layer_cell))| {
                             let distance = last_layer_cell.distance + leg_dist;
                             GraphCell {
                                 prev_index: prev_index + tp_index,
@@ -113,31 +119,33 @@ impl Graph {
 
     // Build a layered graph for a fixed start point which can be traversed
     // to find the best solution for the given start point.
-    // Penalize finish points that to not adhere to the 1000m altitude rule
-    pub fn for_candidate<T: Point>(
-        candidate: &StartCandidate,
-        dist_matrix: &[Vec<f32>],
+    // Penalize finish points that to not adhere to the constraints for a route with max_distance
+    pub fn for_candidate<T: Point, D: DistanceMatrix<F> + ?Sized>(
+        candidate: &StartCandidate<F>,
+        dist_matrix: &D,
         route: &[T],
         legs: usize,
+        constraints: Constraints,
+        max_distance: F,
     ) -> Self {
-        let mut graph: Vec<Vec<GraphCell>> = Vec::with_capacity(legs);
+        let mut graph: Vec<Vec<GraphCell<F>>> = Vec::with_capacity(legs);
 
-        let layer: Vec<GraphCell> = opt_par_iter(dist_matrix)
-            .enumerate()
-            .map(|(tp_index, distances)| {
-                distances
-                    .iter()
+        let layer: Vec<GraphCell<F>> = opt_par_range(0..dist_matrix.points())
+            .map(|tp_index| {
+                dist_matrix
+                    .row(tp_index)
                     .enumerate()
-                    .map(|(finish_index, &distance)| {
-                        if route.valid(candidate.start, finish_index + tp_index) {
+                    .map(|(finish_index, distance)| {
+                        let finish = finish_index + tp_index;
+                        if route.valid(candidate.start, finish, constraints, max_distance) {
                             GraphCell {
-                                prev_index: finish_index + tp_index,
+                                prev_index: finish,
                                 distance,
                             }
                         } else {
                             GraphCell {
-                                prev_index: finish_index + tp_index,
-                                distance: distance - 100_000.0,
+                                prev_index: finish,
+                                distance: distance - F::from_f64(100_000.0),
                             }
                         }
                     })
@@ -159,14 +167,13 @@ impl Graph {
             // that results in the highest total distance?
 
             let last_layer = &graph[layer_index - 1];
-            let layer: Vec<GraphCell> = opt_par_iter(dist_matrix)
-                .enumerate()
-                .map(|(tp_index, distances)| {
-                    distances
-                        .iter()
+            let layer: Vec<GraphCell<F>> = opt_par_range(0..dist_matrix.points())
+                .map(|tp_index| {
+                    dist_matrix
+                        .row(tp_index)
                         .zip(last_layer.iter().skip(tp_index))
                         .enumerate()
-                        .map(|(prev_index, (&leg_dist, last_layer_cell))| {
+                        .map(|(prev_index, (leg_dist, last_layer_cell))| {
                             let distance = last_layer_cell.distance + leg_dist;
                             GraphCell {
                                 prev_index: prev_index + tp_index,
@@ -184,7 +191,7 @@ impl Graph {
     }
 
     // Iterate the graph to find the path which maximizes the distance between its elements.
-    // Respect the 1000m altitude restriction.
+    // Respect the constraints.
     //
     // Note: This function does not guarantee optimality. For every endpoint, it will find the best path without
     // the altitude constraint and disregard the ones that do not satisfy the constraint. This is not equivalent to
@@ -192,7 +199,12 @@ impl Graph {
     // The result of this function can be used as a lower bound for a more complex optimization algorithm.
     //
     // If the graph has been build using Graph::for_start, the result ensures optimality for the given start point.
-    pub fn find_best_valid_solution<T: Point>(&self, route: &[T]) -> OptimizationResult {
+    // Return None if no path complies with the constraints.
+    pub fn find_best_valid_solution<T: Point>(
+        &self,
+        route: &[T],
+        constraints: Constraints,
+    ) -> Option<OptimizationResult<F>> {
         let last_graph_row = self.g.last().unwrap();
         let offset = route.len() - last_graph_row.len();
 
@@ -200,66 +212,79 @@ impl Graph {
             .iter()
             .enumerate()
             .filter_map(|(index, cell)| {
-                let iter = GraphIterator {
-                    graph: self,
-                    next: Some((self.g.len(), index + offset)),
-                    offset,
-                };
-
-                let mut path = iter.collect::<Vec<_>>();
-                if *path.first().unwrap() > *path.last().unwrap() {
-                    path.reverse();
-                }
-                if route.valid(path[0], path[path.len() - 1]) {
+                let path = self.path(index + offset, offset);
+                if cell.distance > F::neg_infinity()
+                    && route.valid(path[0], path[path.len() - 1], constraints, cell.distance)
+                {
                     Some(OptimizationResult {
                         distance: cell.distance,
                         path,
+                        legs: Vec::new(),
                     })
                 } else {
                     None
                 }
             })
             .max_by_key(|result| OrdVar::new_checked(result.distance))
-            .unwrap()
+    }
+
+    // Return the path with the maximum distance that starts at the given point.
+    // For a graph build with Graph::for_candidate, this is the best path for the candidate
+    // among all finish points that have not been penalized.
+    pub fn find_solution_from<T: Point>(&self, route: &[T], start: usize) -> OptimizationResult<F> {
+        let last_graph_row = self.g.last().unwrap();
+        let offset = route.len() - last_graph_row.len();
+        OptimizationResult {
+            distance: last_graph_row[start - offset].distance,
+            path: self.path(start, offset),
+            legs: Vec::new(),
+        }
+    }
+
+    // Iterate the graph starting at the given index in the last row
+    fn path(&self, index: usize, offset: usize) -> Path {
+        let iter = GraphIterator {
+            graph: self,
+            next: Some((self.g.len(), index)),
+            offset,
+        };
+
+        let mut path = iter.collect::<Vec<_>>();
+        if *path.first().unwrap() > *path.last().unwrap() {
+            path.reverse();
+        }
+        path
     }
 
     // Iterate the graph to find the path which maximizes the distance between its elements.
-    // Respect the 1000m altitude restriction.
-    pub fn find_best_solution<T: Point>(&self, route: &[T]) -> OptimizationResult {
+    // Ignore the constraints.
+    pub fn find_best_solution<T: Point>(&self, route: &[T]) -> OptimizationResult<F> {
         let last_graph_row = self.g.last().unwrap();
         let offset = route.len() - last_graph_row.len();
 
         last_graph_row
             .iter()
             .enumerate()
-            .filter_map(|(index, cell)| {
-                let iter = GraphIterator {
-                    graph: self,
-                    next: Some((self.g.len(), index + offset)),
-                    offset,
-                };
-
-                let mut path = iter.collect::<Vec<_>>();
-                if *path.first().unwrap() > *path.last().unwrap() {
-                    path.reverse();
-                }
-                Some(OptimizationResult {
+            .map(|(index, cell)| {
+                let path = self.path(index + offset, offset);
+                OptimizationResult {
                     distance: cell.distance,
                     path,
-                })
+                    legs: Vec::new(),
+                }
             })
             .max_by_key(|result| OrdVar::new_checked(result.distance))
             .unwrap()
     }
 }
 
-struct GraphIterator<'a> {
-    graph: &'a Graph,
+struct GraphIterator<'a, F> {
+    graph: &'a Graph<F>,
     next: Option<(usize, usize)>,
     offset: usize,
 }
 
-impl Iterator for GraphIterator<'_> {
+impl<F> Iterator for GraphIterator<'_, F> {
     type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
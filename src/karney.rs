@@ -0,0 +1,774 @@
+// The inverse geodesic problem on the WGS-84 ellipsoid after Karney, Algorithms for geodesics (2013),
+// https://arxiv.org/pdf/1109.4448.pdf, ported from the C implementation of GeographicLib.
+//
+// Unlike Vincenty's method, the solution always converges, also for nearly antipodal points:
+// Newton's method on the azimuth is started from a good estimate (solving the astroid problem
+// close to the antipode) and falls back to bisection of a bracket around the root.
+// The series are expanded to the sixth order of the third flattening, which is accurate to
+// a few nanometers. The calculation is always done in double precision.
+
+use std::f64::consts::PI;
+
+use crate::float::Float;
+use crate::point::Point;
+
+// Order of the series expansions
+const ORDER: usize = 6;
+const TOL0: f64 = f64::EPSILON;
+const MAX_NEWTON: usize = 20;
+// Newton's method plus enough bisections to reach the precision of f64 (53 bits)
+const MAX_ITERATIONS: usize = MAX_NEWTON + 53 + 10;
+
+// Return the distance in km and the initial bearing in degrees [0, 360) from fix1 to fix2
+pub fn karney_inverse<F: Float, T: Point>(fix1: &T, fix2: &T) -> (F, F) {
+    let (distance, azimuth) = Geodesic::wgs84().inverse(
+        fix1.latitude(),
+        fix1.longitude(),
+        fix2.latitude(),
+        fix2.longitude(),
+    );
+    (
+        F::from_f64(distance / 1000.),
+        F::from_f64((azimuth + 360.) % 360.),
+    )
+}
+
+pub fn karney_distance<F: Float, T: Point>(fix1: &T, fix2: &T) -> F {
+    karney_inverse(fix1, fix2).0
+}
+
+struct Geodesic {
+    a: f64,
+    f: f64,
+    f1: f64,
+    ep2: f64,
+    n: f64,
+    b: f64,
+    etol2: f64,
+    a3x: [f64; ORDER],
+    c3x: [f64; ORDER * (ORDER - 1) / 2],
+}
+
+// The results of Lambda12 for an azimuth alpha1 at the first point
+struct Lambda {
+    lam12: f64,
+    salp2: f64,
+    calp2: f64,
+    sig12: f64,
+    ssig1: f64,
+    csig1: f64,
+    ssig2: f64,
+    csig2: f64,
+    eps: f64,
+    dlam12: f64,
+}
+
+impl Geodesic {
+    #[allow(clippy::excessive_precision)]
+    fn wgs84() -> Self {
+        Geodesic::new(6378137., 1. / 298.257223563)
+    }
+
+    fn new(a: f64, f: f64) -> Self {
+        let f1 = 1. - f;
+        let e2 = f * (2. - f);
+        let n = f / (2. - f);
+        let tol2 = TOL0.sqrt();
+        let mut geodesic = Geodesic {
+            a,
+            f,
+            f1,
+            ep2: e2 / (f1 * f1),
+            n,
+            b: a * f1,
+            etol2: 0.1 * tol2 / ((f.abs().max(0.001) * (1. - f / 2.).min(1.)) / 2.).sqrt(),
+            a3x: [0.; ORDER],
+            c3x: [0.; ORDER * (ORDER - 1) / 2],
+        };
+        geodesic.a3_coefficients();
+        geodesic.c3_coefficients();
+        geodesic
+    }
+
+    // Return the distance in m and the azimuth in degrees at the first point
+    fn inverse(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> (f64, f64) {
+        // the longitude difference in [0, 180], the transformations to the canonical form
+        // are registered in the signs
+        let mut lon12 = ang_normalize(lon2 - lon1);
+        let mut lonsign = if lon12.is_sign_negative() { -1. } else { 1. };
+        lon12 *= lonsign;
+        let lam12 = lon12.to_radians();
+        let (slam12, clam12) = sincosd(lon12);
+        // the supplementary longitude difference
+        let lon12s = 180. - lon12;
+
+        // if really close to the equator, treat as on equator
+        let mut lat1 = ang_round(lat1);
+        let mut lat2 = ang_round(lat2);
+        // swap points so that the point with the higher (absolute) latitude is point 1
+        let swapp = if lat1.abs() < lat2.abs() { -1. } else { 1. };
+        if swapp < 0. {
+            lonsign *= -1.;
+            std::mem::swap(&mut lat1, &mut lat2);
+        }
+        // make lat1 <= -0
+        let latsign = if lat1.is_sign_negative() { 1. } else { -1. };
+        lat1 *= latsign;
+        lat2 *= latsign;
+
+        let (mut sbet1, mut cbet1) = sincosd(lat1);
+        sbet1 *= self.f1;
+        norm2(&mut sbet1, &mut cbet1);
+        // ensure cbet1 = +epsilon at the poles
+        cbet1 = cbet1.max(TINY);
+
+        let (mut sbet2, mut cbet2) = sincosd(lat2);
+        sbet2 *= self.f1;
+        norm2(&mut sbet2, &mut cbet2);
+        cbet2 = cbet2.max(TINY);
+
+        // force bet2 = +/- bet1 exactly if they are equal within the precision
+        if cbet1 < -sbet1 {
+            if cbet2 == cbet1 {
+                sbet2 = sbet1.abs().copysign(sbet2);
+            }
+        } else if sbet2.abs() == -sbet1 {
+            cbet2 = cbet1;
+        }
+
+        let dn1 = (1. + self.ep2 * sbet1 * sbet1).sqrt();
+        let dn2 = (1. + self.ep2 * sbet2 * sbet2).sqrt();
+
+        let mut s12x = 0.;
+        let mut salp1 = 0.;
+        let mut calp1 = 0.;
+        let mut salp2 = 0.;
+        let mut calp2 = 0.;
+        let mut meridian = lat1 == -90. || slam12 == 0.;
+
+        if meridian {
+            // the endpoints are on a single full meridian, so the geodesic might lie on it
+            calp1 = clam12;
+            salp1 = slam12;
+            calp2 = 1.;
+            salp2 = 0.;
+
+            // tan(bet) = tan(sig) * cos(alp)
+            let (ssig1, csig1) = (sbet1, calp1 * cbet1);
+            let (ssig2, csig2) = (sbet2, calp2 * cbet2);
+
+            // max may return -0, which would turn the atan2 into -pi for antipodal points on the equator
+            let sig12 =
+                ((csig1 * ssig2 - ssig1 * csig2).max(0.) + 0.).atan2(csig1 * csig2 + ssig1 * ssig2);
+            let (s12b, m12b) = self.lengths(self.n, sig12, ssig1, csig1, dn1, ssig2, csig2, dn2);
+            // for sig12 > pi/2 the meridian is not the shortest path, if the reduced length is negative
+            if sig12 < 1. || m12b >= 0. {
+                // coincident points
+                if sig12 < 3. * TINY || (sig12 < TOL0 && (s12b < 0. || m12b < 0.)) {
+                    s12x = 0.;
+                } else {
+                    s12x = s12b * self.b;
+                }
+            } else {
+                meridian = false;
+            }
+        }
+
+        if !meridian && sbet1 == 0. && (self.f <= 0. || lon12s >= self.f * 180.) {
+            // the geodesic runs along the equator
+            calp1 = 0.;
+            calp2 = 0.;
+            salp1 = 1.;
+            salp2 = 1.;
+            s12x = self.a * lam12;
+        } else if !meridian {
+            // the geodesic is neither meridional nor equatorial, find a starting point for Newton's method
+            let start = self.inverse_start(sbet1, cbet1, sbet2, cbet2, lam12, slam12, clam12);
+            salp1 = start.salp1;
+            calp1 = start.calp1;
+            if let Some((sig12, salp2s, calp2s, dnm)) = start.short {
+                // short lines
+                s12x = sig12 * self.b * dnm;
+                salp2 = salp2s;
+                calp2 = calp2s;
+            } else {
+                // Newton's method on f(alp1) = lambda12(alp1) - lam12 = 0, which has exactly one root
+                // in (0, pi) with a positive derivative. The root is bracketed by (alp1a, alp1b),
+                // if a Newton step fails, the iteration continues with the middle of the bracket.
+                let mut salp1a = TINY;
+                let mut calp1a = 1.;
+                let mut salp1b = TINY;
+                let mut calp1b = -1.;
+                let mut tripn = false;
+                let mut tripb = false;
+                let mut iteration = 0;
+                let lambda = loop {
+                    let lambda = self.lambda12(
+                        sbet1,
+                        cbet1,
+                        dn1,
+                        sbet2,
+                        cbet2,
+                        dn2,
+                        salp1,
+                        calp1,
+                        slam12,
+                        clam12,
+                        iteration < MAX_NEWTON,
+                    );
+                    let v = lambda.lam12;
+                    // also escape with NaNs
+                    if tripb
+                        || v.is_nan()
+                        || v.abs() < if tripn { 8. } else { 1. } * TOL0
+                        || iteration == MAX_ITERATIONS
+                    {
+                        break lambda;
+                    }
+                    // update the bracket
+                    if v > 0. && (iteration > MAX_NEWTON || calp1 / salp1 > calp1b / salp1b) {
+                        salp1b = salp1;
+                        calp1b = calp1;
+                    } else if v < 0. && (iteration > MAX_NEWTON || calp1 / salp1 < calp1a / salp1a)
+                    {
+                        salp1a = salp1;
+                        calp1a = calp1;
+                    }
+                    iteration += 1;
+                    if iteration <= MAX_NEWTON && lambda.dlam12 > 0. {
+                        let dalp1 = -v / lambda.dlam12;
+                        if dalp1.abs() < PI {
+                            let (sdalp1, cdalp1) = dalp1.sin_cos();
+                            let nsalp1 = salp1 * cdalp1 + calp1 * sdalp1;
+                            if nsalp1 > 0. {
+                                calp1 = calp1 * cdalp1 - salp1 * sdalp1;
+                                salp1 = nsalp1;
+                                norm2(&mut salp1, &mut calp1);
+                                // without quadratic convergence, the conditions are based on epsilon
+                                tripn = v.abs() <= 16. * TOL0;
+                                continue;
+                            }
+                        }
+                    }
+                    // the derivative was not positive or the new value was outside of the legal
+                    // range, continue with the middle of the bracket
+                    salp1 = (salp1a + salp1b) / 2.;
+                    calp1 = (calp1a + calp1b) / 2.;
+                    norm2(&mut salp1, &mut calp1);
+                    tripn = false;
+                    let tolb = TOL0 * TOL0.sqrt();
+                    tripb = (salp1a - salp1).abs() + (calp1a - calp1) < tolb
+                        || (salp1 - salp1b).abs() + (calp1 - calp1b) < tolb;
+                };
+                let (s12b, _) = self.lengths(
+                    lambda.eps,
+                    lambda.sig12,
+                    lambda.ssig1,
+                    lambda.csig1,
+                    dn1,
+                    lambda.ssig2,
+                    lambda.csig2,
+                    dn2,
+                );
+                s12x = s12b * self.b;
+                salp2 = lambda.salp2;
+                calp2 = lambda.calp2;
+            }
+        }
+
+        // undo the transformations to the canonical form
+        if swapp < 0. {
+            std::mem::swap(&mut salp1, &mut salp2);
+            std::mem::swap(&mut calp1, &mut calp2);
+        }
+        salp1 *= swapp * lonsign;
+        calp1 *= swapp * latsign;
+
+        (0. + s12x, salp1.atan2(calp1).to_degrees())
+    }
+
+    fn a3_coefficients(&mut self) {
+        // coefficients of A3 for eps^5 down to eps^0, each a polynomial in n followed by the divisor
+        const COEFF: [f64; 18] = [
+            -3., 128., -2., -3., 64., -1., -3., -1., 16., 3., -1., -2., 8., 1., -1., 2., 1., 1.,
+        ];
+        let mut offset = 0;
+        for (k, j) in (0..ORDER).rev().enumerate() {
+            let m = (ORDER - j - 1).min(j);
+            self.a3x[k] = polyval(&COEFF[offset..=offset + m], self.n) / COEFF[offset + m + 1];
+            offset += m + 2;
+        }
+    }
+
+    fn c3_coefficients(&mut self) {
+        // coefficients of C3[l] for eps^5 down to eps^l, each a polynomial in n followed by the divisor
+        const COEFF: [f64; 45] = [
+            3., 128., 2., 5., 128., -1., 3., 3., 64., -1., 0., 1., 8., -1., 1., 4., 5., 256., 1.,
+            3., 128., -3., -2., 3., 64., 1., -3., 2., 32., 7., 512., -10., 9., 384., 5., -9., 5.,
+            192., 7., 512., -14., 7., 128., 21., 2560.,
+        ];
+        let mut offset = 0;
+        let mut k = 0;
+        for l in 1..ORDER {
+            for j in (l..ORDER).rev() {
+                let m = (ORDER - j - 1).min(j);
+                self.c3x[k] = polyval(&COEFF[offset..=offset + m], self.n) / COEFF[offset + m + 1];
+                k += 1;
+                offset += m + 2;
+            }
+        }
+    }
+
+    fn a3f(&self, eps: f64) -> f64 {
+        polyval(&self.a3x, eps)
+    }
+
+    // Coefficients C3[1] to C3[ORDER - 1]
+    fn c3f(&self, eps: f64) -> [f64; ORDER + 1] {
+        let mut c = [0.; ORDER + 1];
+        let mut mult = 1.;
+        let mut offset = 0;
+        for (l, c) in c.iter_mut().enumerate().take(ORDER).skip(1) {
+            let m = ORDER - l - 1;
+            mult *= eps;
+            *c = mult * polyval(&self.c3x[offset..=offset + m], eps);
+            offset += m + 1;
+        }
+        c
+    }
+
+    // Return the distance and the reduced length, both divided by b
+    #[allow(clippy::too_many_arguments)]
+    fn lengths(
+        &self,
+        eps: f64,
+        sig12: f64,
+        ssig1: f64,
+        csig1: f64,
+        dn1: f64,
+        ssig2: f64,
+        csig2: f64,
+        dn2: f64,
+    ) -> (f64, f64) {
+        let a1 = a1m1f(eps);
+        let c1 = c1f(eps);
+        let a2 = a2m1f(eps);
+        let c2 = c2f(eps);
+        let m0 = a1 - a2;
+        let (a1, a2) = (1. + a1, 1. + a2);
+        let b1 = sin_series(ssig2, csig2, &c1) - sin_series(ssig1, csig1, &c1);
+        let s12b = a1 * (sig12 + b1);
+        let b2 = sin_series(ssig2, csig2, &c2) - sin_series(ssig1, csig1, &c2);
+        let j12 = m0 * sig12 + (a1 * b1 - a2 * b2);
+        // the parentheses ensure an accurate cancellation for coincident points
+        let m12b = dn2 * (csig1 * ssig2) - dn1 * (ssig1 * csig2) - csig1 * csig2 * j12;
+        (s12b, m12b)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn inverse_start(
+        &self,
+        sbet1: f64,
+        cbet1: f64,
+        sbet2: f64,
+        cbet2: f64,
+        lam12: f64,
+        slam12: f64,
+        clam12: f64,
+    ) -> Start {
+        // bet12 = bet2 - bet1 in [0, pi), bet12a = bet2 + bet1 in (-pi, 0]
+        let sbet12 = sbet2 * cbet1 - cbet2 * sbet1;
+        let cbet12 = cbet2 * cbet1 + sbet2 * sbet1;
+        let sbet12a = sbet2 * cbet1 + cbet2 * sbet1;
+        let shortline = cbet12 >= 0. && sbet12 < 0.5 && cbet2 * lam12 < 0.5;
+        let mut dnm = 0.;
+        let (mut somg12, mut comg12) = if shortline {
+            let mut sbetm2 = (sbet1 + sbet2) * (sbet1 + sbet2);
+            sbetm2 /= sbetm2 + (cbet1 + cbet2) * (cbet1 + cbet2);
+            dnm = (1. + self.ep2 * sbetm2).sqrt();
+            (lam12 / (self.f1 * dnm)).sin_cos()
+        } else {
+            (slam12, clam12)
+        };
+
+        let mut salp1 = cbet2 * somg12;
+        let mut calp1 = if comg12 >= 0. {
+            sbet12 + cbet2 * sbet1 * somg12 * somg12 / (1. + comg12)
+        } else {
+            sbet12a - cbet2 * sbet1 * somg12 * somg12 / (1. - comg12)
+        };
+
+        let ssig12 = salp1.hypot(calp1);
+        let csig12 = sbet1 * sbet2 + cbet1 * cbet2 * comg12;
+
+        let mut short = None;
+        if shortline && ssig12 < self.etol2 {
+            // really short lines
+            let mut salp2 = cbet1 * somg12;
+            let mut calp2 = sbet12
+                - cbet1
+                    * sbet2
+                    * if comg12 >= 0. {
+                        somg12 * somg12 / (1. + comg12)
+                    } else {
+                        1. - comg12
+                    };
+            norm2(&mut salp2, &mut calp2);
+            short = Some((ssig12.atan2(csig12), salp2, calp2, dnm));
+        } else if self.n.abs() > 0.1
+            || csig12 >= 0.
+            || ssig12 >= 6. * self.n.abs() * PI * cbet1 * cbet1
+        {
+            // the zeroth order spherical approximation is good enough
+        } else {
+            // scale lam12 and bet2 to a coordinate system where the antipodal point is at the origin
+            // and the singular point is at y = 0, x = -1
+            let lam12x = (-slam12).atan2(-clam12);
+            let k2 = sbet1 * sbet1 * self.ep2;
+            let eps = k2 / (2. * (1. + (1. + k2).sqrt()) + k2);
+            let lamscale = self.f * cbet1 * self.a3f(eps) * PI;
+            let betscale = lamscale * cbet1;
+            let x = lam12x / lamscale;
+            let y = sbet12a / betscale;
+
+            if y > -200. * TOL0 && x > -1. - 1000. * TOL0.sqrt() {
+                // strip near the cut
+                salp1 = (-x).min(1.);
+                calp1 = -(1. - salp1 * salp1).sqrt();
+            } else {
+                // estimate omg12 by solving the astroid problem, omg12 is close to pi,
+                // so use omg12a = pi - omg12
+                let k = astroid(x, y);
+                let omg12a = lamscale * (-x * k / (1. + k));
+                somg12 = omg12a.sin();
+                comg12 = -omg12a.cos();
+                // update the spherical estimate of alp1 using omg12 instead of lam12
+                salp1 = cbet2 * somg12;
+                calp1 = sbet12a - cbet2 * sbet1 * somg12 * somg12 / (1. - comg12);
+            }
+        }
+        // sanity check of the starting guess, NaNs are passed through
+        if salp1 > 0. || salp1.is_nan() {
+            norm2(&mut salp1, &mut calp1);
+        } else {
+            salp1 = 1.;
+            calp1 = 0.;
+        }
+        Start {
+            salp1,
+            calp1,
+            short,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn lambda12(
+        &self,
+        sbet1: f64,
+        cbet1: f64,
+        dn1: f64,
+        sbet2: f64,
+        cbet2: f64,
+        dn2: f64,
+        salp1: f64,
+        calp1: f64,
+        slam120: f64,
+        clam120: f64,
+        diffp: bool,
+    ) -> Lambda {
+        // break the degeneracy of the equatorial line
+        let calp1 = if sbet1 == 0. && calp1 == 0. {
+            -TINY
+        } else {
+            calp1
+        };
+
+        // sin(alp1) * cos(bet1) = sin(alp0)
+        let salp0 = salp1 * cbet1;
+        let calp0 = calp1.hypot(salp1 * sbet1);
+
+        // tan(bet1) = tan(sig1) * cos(alp1), tan(omg1) = sin(alp0) * tan(sig1)
+        let mut ssig1 = sbet1;
+        let somg1 = salp0 * sbet1;
+        let mut csig1 = calp1 * cbet1;
+        let comg1 = csig1;
+        norm2(&mut ssig1, &mut csig1);
+
+        // enforce symmetries in the case abs(bet2) = -bet1
+        let salp2 = if cbet2 != cbet1 { salp0 / cbet2 } else { salp1 };
+        let calp2 = if cbet2 != cbet1 || sbet2.abs() != -sbet1 {
+            ((calp1 * cbet1) * (calp1 * cbet1)
+                + if cbet1 < -sbet1 {
+                    (cbet2 - cbet1) * (cbet1 + cbet2)
+                } else {
+                    (sbet1 - sbet2) * (sbet1 + sbet2)
+                })
+            .sqrt()
+                / cbet2
+        } else {
+            calp1.abs()
+        };
+        // tan(bet2) = tan(sig2) * cos(alp2), tan(omg2) = sin(alp0) * tan(sig2)
+        let mut ssig2 = sbet2;
+        let somg2 = salp0 * sbet2;
+        let mut csig2 = calp2 * cbet2;
+        let comg2 = csig2;
+        norm2(&mut ssig2, &mut csig2);
+
+        // sig12 = sig2 - sig1, limited to [0, pi]
+        let sig12 =
+            ((csig1 * ssig2 - ssig1 * csig2).max(0.) + 0.).atan2(csig1 * csig2 + ssig1 * ssig2);
+        // omg12 = omg2 - omg1, limited to [0, pi]
+        let somg12 = (comg1 * somg2 - somg1 * comg2).max(0.);
+        let comg12 = comg1 * comg2 + somg1 * somg2;
+        // eta = omg12 - lam120
+        let eta = (somg12 * clam120 - comg12 * slam120).atan2(comg12 * clam120 + somg12 * slam120);
+        let k2 = calp0 * calp0 * self.ep2;
+        let eps = k2 / (2. * (1. + (1. + k2).sqrt()) + k2);
+        let c3 = self.c3f(eps);
+        let b312 = sin_series(ssig2, csig2, &c3[..ORDER]) - sin_series(ssig1, csig1, &c3[..ORDER]);
+        let domg12 = -self.f * self.a3f(eps) * salp0 * (sig12 + b312);
+        let lam12 = eta + domg12;
+
+        let dlam12 = if !diffp {
+            0.
+        } else if calp2 == 0. {
+            -2. * self.f1 * dn1 / sbet1
+        } else {
+            let (_, m12b) = self.lengths(eps, sig12, ssig1, csig1, dn1, ssig2, csig2, dn2);
+            m12b * self.f1 / (calp2 * cbet2)
+        };
+
+        Lambda {
+            lam12,
+            salp2,
+            calp2,
+            sig12,
+            ssig1,
+            csig1,
+            ssig2,
+            csig2,
+            eps,
+            dlam12,
+        }
+    }
+}
+
+// The starting point for Newton's method. For short lines, the solution is known directly:
+// sig12, salp2, calp2 and dnm
+struct Start {
+    salp1: f64,
+    calp1: f64,
+    short: Option<(f64, f64, f64, f64)>,
+}
+
+const TINY: f64 = 1.4916681462400413e-154; // sqrt(f64::MIN_POSITIVE)
+
+// Evaluate the polynomial with the coefficients p (highest order first) at x
+fn polyval(p: &[f64], x: f64) -> f64 {
+    p.iter().fold(0., |y, &coefficient| y * x + coefficient)
+}
+
+// Evaluate sum(c[i] * sin(2 * i * x), i = 1..c.len()) with Clenshaw summation, c[0] is unused
+fn sin_series(sinx: f64, cosx: f64, c: &[f64]) -> f64 {
+    // 2 * cos(2 * x)
+    let ar = 2. * (cosx - sinx) * (cosx + sinx);
+    let (mut b1, mut b2) = (0., 0.);
+    for &coefficient in c[1..].iter().rev() {
+        let b0 = coefficient + ar * b1 - b2;
+        b2 = b1;
+        b1 = b0;
+    }
+    2. * sinx * cosx * b1
+}
+
+// (1 - eps) * A1 - 1
+fn a1m1f(eps: f64) -> f64 {
+    let eps2 = eps * eps;
+    let t = polyval(&[1., 4., 64., 0.], eps2) / 256.;
+    (t + eps) / (1. - eps)
+}
+
+// (1 + eps) * A2 - 1
+fn a2m1f(eps: f64) -> f64 {
+    let eps2 = eps * eps;
+    let t = polyval(&[-11., -28., -192., 0.], eps2) / 256.;
+    (t - eps) / (1. + eps)
+}
+
+// Coefficients C1[1] to C1[6]
+fn c1f(eps: f64) -> [f64; ORDER + 1] {
+    const COEFF: [f64; 18] = [
+        -1., 6., -16., 32., -9., 64., -128., 2048., 9., -16., 768., 3., -5., 512., -7., 1280., -7.,
+        2048.,
+    ];
+    series_coefficients(eps, &COEFF)
+}
+
+// Coefficients C2[1] to C2[6]
+fn c2f(eps: f64) -> [f64; ORDER + 1] {
+    const COEFF: [f64; 18] = [
+        1., 2., 16., 32., 35., 64., 384., 2048., 15., 80., 768., 7., 35., 512., 63., 1280., 77.,
+        2048.,
+    ];
+    series_coefficients(eps, &COEFF)
+}
+
+// The coefficients c[l] / eps^l are polynomials in eps^2, each followed by its divisor
+fn series_coefficients(eps: f64, coeff: &[f64]) -> [f64; ORDER + 1] {
+    let eps2 = eps * eps;
+    let mut c = [0.; ORDER + 1];
+    let mut d = eps;
+    let mut offset = 0;
+    for (l, c) in c.iter_mut().enumerate().skip(1) {
+        let m = (ORDER - l) / 2;
+        *c = d * polyval(&coeff[offset..=offset + m], eps2) / coeff[offset + m + 1];
+        offset += m + 2;
+        d *= eps;
+    }
+    c
+}
+
+// Solve k^4 + 2 * k^3 - (x^2 + y^2 - 1) * k^2 - 2 * y^2 * k - y^2 = 0 for the positive root k
+fn astroid(x: f64, y: f64) -> f64 {
+    let p = x * x;
+    let q = y * y;
+    let r = (p + q - 1.) / 6.;
+    if q == 0. && r <= 0. {
+        // y = 0 with |x| <= 1
+        return 0.;
+    }
+    // avoid a division by zero for r = 0 by multiplying the equations for s and t by r^3 and r
+    let s = p * q / 4.;
+    let r2 = r * r;
+    let r3 = r * r2;
+    // the discriminant of the quadratic equation for t^3, zero on the evolute curve p^(1/3) + q^(1/3) = 1
+    let disc = s * (s + 2. * r3);
+    let mut u = r;
+    if disc >= 0. {
+        // pick the sign of the square root to maximize abs(t3), which avoids cancellation
+        let mut t3 = s + r3;
+        t3 += if t3 < 0. { -disc.sqrt() } else { disc.sqrt() };
+        let t = t3.cbrt();
+        u += t + if t != 0. { r2 / t } else { 0. };
+    } else {
+        // t is complex, but u is real
+        let angle = (-disc).sqrt().atan2(-(s + r3));
+        u += 2. * r * (angle / 3.).cos();
+    }
+    let v = (u * u + q).sqrt();
+    // avoid the loss of accuracy for u < 0
+    let uv = if u < 0. { q / (v - u) } else { u + v };
+    let w = (uv - q) / (2. * v);
+    uv / ((uv + w * w).sqrt() + w)
+}
+
+// Normalize the vector (x, y) to unit length
+fn norm2(x: &mut f64, y: &mut f64) {
+    let r = x.hypot(*y);
+    *x /= r;
+    *y /= r;
+}
+
+// Normalize an angle to (-180, 180]
+fn ang_normalize(x: f64) -> f64 {
+    let y = x - 360. * (x / 360.).round();
+    if y.abs() == 180. {
+        180_f64.copysign(x)
+    } else {
+        y
+    }
+}
+
+// Round tiny angles to zero, so that points very close to the equator are treated as on it
+fn ang_round(x: f64) -> f64 {
+    let z = 1. / 16.;
+    let y = x.abs();
+    let w = z - y;
+    let y = if w > 0. { z - w } else { y };
+    y.copysign(x)
+}
+
+// Sine and cosine of an angle in degrees, exact for multiples of 90 degrees
+fn sincosd(x: f64) -> (f64, f64) {
+    let quadrant = (x / 90.).round();
+    let (s, c) = (x - 90. * quadrant).to_radians().sin_cos();
+    let (s, c) = match (quadrant as i64).rem_euclid(4) {
+        0 => (s, c),
+        1 => (c, -s),
+        2 => (-s, -c),
+        _ => (-c, s),
+    };
+    // convert -0 to 0
+    (s + 0., c + 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PointImpl;
+    use crate::vincenty::vincenty_inverse;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn point(latitude: f64, longitude: f64) -> PointImpl {
+        PointImpl {
+            latitude,
+            longitude,
+            altitude: 0,
+            timestamp: None,
+            engine_noise: None,
+        }
+    }
+
+    #[test]
+    fn matches_reference_values() {
+        // JFK to LHR and Wellington to Salamanca (nearly antipodal), from the documentation of GeographicLib
+        let (distance, bearing): (f64, f64) =
+            karney_inverse(&point(40.6, -73.8), &point(51.6, -0.5));
+        assert_approx_eq!(distance, 5551.759400319, 1e-9);
+        assert_approx_eq!(bearing, 51.198882845579824, 1e-9);
+
+        let (distance, bearing): (f64, f64) =
+            karney_inverse(&point(-41.32, 174.81), &point(40.96, -5.50));
+        assert_approx_eq!(distance, 19959.679267353, 1e-9);
+        assert_approx_eq!(bearing, 161.067669986160, 1e-9);
+    }
+
+    #[test]
+    fn special_cases() {
+        // along the equator, along a meridian over the pole and coincident points
+        let distance: f64 = karney_distance(&point(0.0, 0.0), &point(0.0, 1.0));
+        assert_approx_eq!(distance, 6378.137 * PI / 180., 1e-9);
+        let distance: f64 = karney_distance(&point(-90.0, 0.0), &point(90.0, 0.0));
+        assert_approx_eq!(distance, 20003.931458625, 1e-9);
+        let distance: f64 = karney_distance(&point(0.0, 0.0), &point(0.0, 180.0));
+        assert_approx_eq!(distance, 20003.931458625, 1e-9);
+        let distance: f64 = karney_distance(&point(45.0, 10.0), &point(45.0, 10.0));
+        assert_eq!(distance, 0.0);
+    }
+
+    #[test]
+    fn matches_vincenty() {
+        let pairs = [
+            ((47.3, 11.2), (47.9, 12.8)),
+            ((-33.9, 18.4), (-30.1, 24.0)),
+            ((60.0, -150.0), (-45.0, 120.0)),
+            ((10.0, 179.9), (10.5, -179.9)),
+        ];
+        for ((lat1, lon1), (lat2, lon2)) in pairs {
+            let (fix1, fix2) = (point(lat1, lon1), point(lat2, lon2));
+            let (distance, bearing): (f64, f64) = karney_inverse(&fix1, &fix2);
+            let (expected, expected_bearing): (f64, f64) = vincenty_inverse(&fix1, &fix2).unwrap();
+            assert_approx_eq!(distance, expected, 1e-6);
+            assert_approx_eq!(bearing, expected_bearing, 1e-6);
+        }
+    }
+
+    #[test]
+    fn converges_for_nearly_antipodal_points() {
+        let (fix1, fix2) = (point(0.0, 0.0), point(0.5, 179.5));
+        assert!(vincenty_inverse::<f64, _>(&fix1, &fix2).is_none());
+        let distance: f64 = karney_distance(&fix1, &fix2);
+        assert!(distance > 19900. && distance < 20003.931458625);
+    }
+}
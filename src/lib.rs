@@ -1,38 +1,23 @@
-use numpy::PyReadonlyArray1;
-use pyo3::prelude::*;
-
 pub mod cache;
+pub mod closing;
+pub mod distance;
+pub mod error;
+pub mod export;
 pub mod flat;
+pub mod flight;
+pub mod float;
 pub mod free;
+pub mod gpx;
 pub mod graph;
+pub mod karney;
+pub mod matrix;
+pub mod out_and_return;
 pub mod parallel;
+pub mod phases;
 pub mod point;
+#[cfg(feature = "python")]
+mod python;
+pub mod reduce;
 pub mod result;
+pub mod triangle;
 pub mod vincenty;
-
-#[pymodule]
-fn score_rs(_py: Python, m: &PyModule) -> PyResult<()> {
-    #[pyfn(m)]
-    #[pyo3(name = "optimize")]
-    fn optimize_py<'py>(
-        longitude: PyReadonlyArray1<'py, f64>,
-        latitude: PyReadonlyArray1<'py, f64>,
-        alt: PyReadonlyArray1<'py, i64>,
-        legs: usize,
-    ) -> PyResult<(Vec<usize>, f32)> {
-        let mut points = Vec::new();
-        let longitude = longitude.as_slice().unwrap();
-        let latitude = latitude.as_slice().unwrap();
-        let alt = alt.as_slice().unwrap();
-        for i in 0..longitude.len() {
-            points.push(point::PointImpl {
-                longitude: longitude[i] as f32,
-                latitude: latitude[i] as f32,
-                altitude: alt[i] as i16,
-            });
-        }
-        let result = free::optimize(&points, 0.0, legs).unwrap();
-        Ok((result.path, result.distance))
-    }
-    Ok(())
-}
@@ -0,0 +1,166 @@
+use flat_projection::FlatPoint;
+use itertools::Either;
+
+use crate::float::Float;
+use crate::parallel::*;
+
+// The distances in kilometers from every point to itself and all following points,
+// which the graphs of the free distance are built from
+pub trait DistanceMatrix<F>: Sync {
+    // The number of points
+    fn points(&self) -> usize;
+
+    // The distances from the point at start to the points start, start + 1, ..
+    fn row(&self, start: usize) -> impl Iterator<Item = F> + '_;
+}
+
+impl<F: Float> DistanceMatrix<F> for [Vec<F>] {
+    fn points(&self) -> usize {
+        self.len()
+    }
+
+    fn row(&self, start: usize) -> impl Iterator<Item = F> + '_ {
+        self[start].iter().copied()
+    }
+}
+
+// Calculate the distances from the flat points whenever a row is needed. This takes memory
+// linear in the number of points, but every layer of a graph calculates all distances again.
+pub struct OnTheFly<'a, F> {
+    flat_points: &'a [FlatPoint<F>],
+    excluded: Option<&'a [bool]>,
+}
+
+impl<'a, F: Float> OnTheFly<'a, F> {
+    // All legs from or to an excluded point get a distance of negative infinity,
+    // just like with exclude_turnpoints on a stored matrix
+    pub fn new(flat_points: &'a [FlatPoint<F>], excluded: Option<&'a [bool]>) -> Self {
+        OnTheFly {
+            flat_points,
+            excluded,
+        }
+    }
+}
+
+impl<F: Float> DistanceMatrix<F> for OnTheFly<'_, F> {
+    fn points(&self) -> usize {
+        self.flat_points.len()
+    }
+
+    fn row(&self, start: usize) -> impl Iterator<Item = F> + '_ {
+        let p1 = &self.flat_points[start];
+        (start..self.flat_points.len()).map(move |stop| match self.excluded {
+            Some(excluded) if excluded[start] || excluded[stop] => F::neg_infinity(),
+            _ => p1.distance(&self.flat_points[stop]),
+        })
+    }
+}
+
+// The distances of a free distance optimization, either stored or calculated on the fly
+pub enum Distances<'a, F> {
+    Stored(Vec<Vec<F>>),
+    OnTheFly(OnTheFly<'a, F>),
+}
+
+impl<'a, F: Float> Distances<'a, F> {
+    // Store the matrix, unless it needs more than memory bytes.
+    // Legs from or to excluded points get a distance of negative infinity.
+    pub fn new(
+        flat_points: &'a [FlatPoint<F>],
+        excluded: &'a [bool],
+        memory: Option<usize>,
+    ) -> Self {
+        let excluded = excluded.contains(&true).then_some(excluded);
+        if memory.is_some_and(|memory| matrix_size::<F>(flat_points.len()) > memory) {
+            return Distances::OnTheFly(OnTheFly::new(flat_points, excluded));
+        }
+        let mut dist_matrix = half_dist_matrix(flat_points);
+        if let Some(excluded) = excluded {
+            exclude_turnpoints(&mut dist_matrix, excluded);
+        }
+        Distances::Stored(dist_matrix)
+    }
+}
+
+impl<F: Float> DistanceMatrix<F> for Distances<'_, F> {
+    fn points(&self) -> usize {
+        match self {
+            Distances::Stored(dist_matrix) => dist_matrix.len(),
+            Distances::OnTheFly(distances) => distances.points(),
+        }
+    }
+
+    fn row(&self, start: usize) -> impl Iterator<Item = F> + '_ {
+        match self {
+            Distances::Stored(dist_matrix) => Either::Left(dist_matrix.row(start)),
+            Distances::OnTheFly(distances) => Either::Right(distances.row(start)),
+        }
+    }
+}
+
+// The number of bytes of the half distance matrix of n points
+pub fn matrix_size<F>(n: usize) -> usize {
+    n * (n + 1) / 2 * std::mem::size_of::<F>() + n * std::mem::size_of::<Vec<F>>()
+}
+
+// Forbid turnpoints on the excluded points: all legs from or to them get a distance of negative
+// infinity, so the graph never selects a path through them.
+fn exclude_turnpoints<F: Float>(dist_matrix: &mut [Vec<F>], excluded: &[bool]) {
+    opt_par_iter_mut(dist_matrix)
+        .enumerate()
+        .for_each(|(start, distances)| {
+            distances
+                .iter_mut()
+                .enumerate()
+                .filter(|(offset, _)| excluded[start] || excluded[start + offset])
+                .for_each(|(_, distance)| *distance = F::neg_infinity());
+        });
+}
+
+// Generate a triangular matrix with the distances in kilometers between all points.
+// For each point, the distance to the following points is saved. This only allows
+// calculation of backward min-marginals
+pub fn half_dist_matrix<F: Float>(flat_points: &[FlatPoint<F>]) -> Vec<Vec<F>> {
+    opt_par_iter(flat_points)
+        .enumerate()
+        .map(|(i, p1)| flat_points[i..].iter().map(|p2| p1.distance(p2)).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_the_fly_matches_stored_matrix() {
+        let flat_points: Vec<FlatPoint<f32>> = (0..20)
+            .map(|i| FlatPoint {
+                x: (i * 7 % 11) as f32,
+                y: (i * 3 % 5) as f32,
+            })
+            .collect();
+        let excluded: Vec<bool> = (0..20).map(|i| i % 6 == 5).collect();
+        let stored = Distances::new(&flat_points, &excluded, None);
+        let on_the_fly = Distances::new(&flat_points, &excluded, Some(0));
+        assert!(matches!(stored, Distances::Stored(_)));
+        assert!(matches!(on_the_fly, Distances::OnTheFly(_)));
+        assert_eq!(on_the_fly.points(), 20);
+        for (start, &excluded) in excluded.iter().enumerate() {
+            let row: Vec<f32> = on_the_fly.row(start).collect();
+            assert_eq!(row, stored.row(start).collect::<Vec<_>>());
+            assert_eq!(row.len(), 20 - start);
+            assert_eq!(row[0] == f32::NEG_INFINITY, excluded);
+        }
+    }
+
+    #[test]
+    fn matrix_size_counts_half_matrix() {
+        assert_eq!(matrix_size::<f32>(0), 0);
+        assert_eq!(
+            matrix_size::<f32>(3),
+            6 * 4 + 3 * std::mem::size_of::<Vec<f32>>()
+        );
+        // the 37k fixes of schunk_1000m.igc need about 2.8 GB
+        assert!(matrix_size::<f32>(37_000) > 2_700_000_000);
+    }
+}
@@ -0,0 +1,190 @@
+// Find the best out-and-return flight in a track: two turnpoints a < b (by index), the distance
+// is flown twice. The track has to be closed, see closing.rs.
+//
+// The layered graph with a single leg provides the maximum distance for every first turnpoint
+// without the closing constraint, which is an upper bound for the closed course. Like the free
+// distance, the start candidates are checked in descending order until the upper bound of the
+// remaining ones is below the current best solution. The distances are calculated on the fly,
+// so long tracks do not need the memory of a distance matrix.
+
+use crate::closing::{find_best_closed, Closing};
+use crate::distance::DistanceModel;
+use crate::error::{check_route, ScoreError};
+use crate::flat::to_flat_points;
+use crate::float::Float;
+use crate::graph::Graph;
+use crate::matrix::{DistanceMatrix, OnTheFly};
+use crate::point::{AltitudeRule, Point};
+use crate::result::ClosedResult;
+
+// Find the two turnpoints which form the out-and-return route with the largest distance.
+// The start and finish of the route must not be further apart than closing_distance km
+// and have to comply with the altitude rule. The distances are calculated with the float type of closing_distance,
+// the distance model measures the legs of the result.
+pub fn optimize<F: Float, T: Point, M: DistanceModel>(
+    route: &[T],
+    closing_distance: F,
+    altitude: AltitudeRule,
+    model: &M,
+) -> Result<ClosedResult<F>, ScoreError> {
+    check_route(route, 2)?;
+    let flat_points = to_flat_points(route);
+    let dist_matrix = OnTheFly::new(&flat_points, None);
+
+    let ((first, second), closing) =
+        find_best_closed(&flat_points, route, closing_distance, altitude, |closing| {
+            find_best_turnpoints(&dist_matrix, closing).map(|(first, second)| {
+                (
+                    (first, second),
+                    F::from_f64(2.0) * flat_points[first].distance(&flat_points[second]),
+                )
+            })
+        })
+        .ok_or(ScoreError::NoValidSolution)?;
+    let (start, stop) = closing.reach[first];
+    ClosedResult::new(vec![first, second], start, stop, route, model)
+}
+
+// Return the two turnpoints with the largest distance between them which can be part of a closed route
+fn find_best_turnpoints<F: Float, D: DistanceMatrix<F> + ?Sized>(
+    dist_matrix: &D,
+    closing: &Closing,
+) -> Option<(usize, usize)> {
+    let graph = Graph::from_distance_matrix(dist_matrix, 1);
+    let mut start_candidates = graph.get_start_candidates(F::zero());
+
+    let mut best: Option<(usize, usize)> = None;
+    let mut best_distance = F::zero();
+    while let Some(candidate) = start_candidates.pop() {
+        if candidate.distance <= best_distance {
+            break;
+        }
+        let last = closing.reach[candidate.start].1;
+        let (offset, distance) = dist_matrix
+            .row(candidate.start)
+            .take(last - candidate.start + 1)
+            .enumerate()
+            .reduce(|best, next| if next.1 > best.1 { next } else { best })
+            .unwrap();
+        if distance > best_distance {
+            best_distance = distance;
+            best = Some((candidate.start, candidate.start + offset));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::Vincenty;
+    use crate::matrix::half_dist_matrix;
+    use crate::point::PointImpl;
+    use assert_approx_eq::assert_approx_eq;
+    use flat_projection::FlatPoint;
+
+    #[test]
+    fn turnpoints_respect_closing() {
+        let flat_points = [
+            FlatPoint { x: 0.0, y: 0.0 },
+            FlatPoint { x: 10.0, y: 0.0 },
+            FlatPoint { x: 0.5, y: 0.0 },
+            FlatPoint { x: 50.0, y: 0.0 },
+        ];
+        let route = vec![
+            PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude: 0,
+                timestamp: None,
+                engine_noise: None,
+            };
+            4
+        ];
+        let dist_matrix = half_dist_matrix(&flat_points);
+        let closing = Closing::new(&flat_points, &route, 1.0, AltitudeRule::default(), 0.0);
+        assert_eq!(
+            find_best_turnpoints(&dist_matrix[..], &closing),
+            Some((0, 1))
+        );
+    }
+
+    // deterministic pseudo random track with altitudes between 0 and 2000m
+    fn random_track(n: usize) -> (Vec<FlatPoint<f32>>, Vec<PointImpl>) {
+        let mut seed: u32 = 7;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as f32 / 65_536.0
+        };
+        let mut position = (0.0, 0.0);
+        (0..n)
+            .map(|_| {
+                position.0 += next() * 20.0 - 10.0;
+                position.1 += next() * 20.0 - 10.0;
+                let point = PointImpl {
+                    latitude: 0.0,
+                    longitude: 0.0,
+                    altitude: (next() * 2000.0) as i16,
+                    timestamp: None,
+                    engine_noise: None,
+                };
+                (
+                    FlatPoint {
+                        x: position.0,
+                        y: position.1,
+                    },
+                    point,
+                )
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn turnpoints_match_brute_force() {
+        let (flat_points, route) = random_track(120);
+        let dist_matrix = half_dist_matrix(&flat_points);
+        for closing_distance in [5.0, 20.0, 50.0] {
+            let closing = Closing::new(
+                &flat_points,
+                &route,
+                closing_distance,
+                AltitudeRule::default(),
+                0.0,
+            );
+            let mut expected = 0.0_f32;
+            for first in 0..route.len() {
+                for second in first..route.len() {
+                    if closing.closes(first, second) {
+                        expected = expected.max(dist_matrix[first][second - first]);
+                    }
+                }
+            }
+            let found = find_best_turnpoints(&dist_matrix[..], &closing)
+                .map_or(0.0, |(first, second)| dist_matrix[first][second - first]);
+            assert_approx_eq!(found, expected, 1e-3);
+            let on_the_fly = OnTheFly::new(&flat_points, None);
+            assert_eq!(
+                find_best_turnpoints(&on_the_fly, &closing),
+                find_best_turnpoints(&dist_matrix[..], &closing)
+            );
+        }
+    }
+
+    #[test]
+    fn out_and_return() {
+        let route = [(50.0, 10.0), (50.5, 10.0), (51.0, 10.0), (50.01, 10.0)]
+            .iter()
+            .map(|&(latitude, longitude)| PointImpl {
+                latitude,
+                longitude,
+                altitude: 0,
+                timestamp: None,
+                engine_noise: None,
+            })
+            .collect::<Vec<_>>();
+        let result = optimize(&route, 2.0_f32, AltitudeRule::default(), &Vincenty).unwrap();
+        assert_eq!(result.path, [0, 2]);
+        assert_eq!((result.start, result.stop), (0, 3));
+        assert_approx_eq!(result.distance, 2.0 * 111.23, 0.1);
+    }
+}
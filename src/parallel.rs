@@ -1,17 +1,34 @@
 use cfg_if::*;
+use std::ops::Range;
 
 cfg_if! {
     if #[cfg(feature = "rayon")] {
         use rayon::slice;
         pub use rayon::prelude::*;
-        pub fn opt_par_iter<T: Sync>(x: &[T]) -> slice::Iter<T> {
+        pub fn opt_par_iter<T: Sync>(x: &[T]) -> slice::Iter<'_, T> {
             x.par_iter()
         }
 
+        pub fn opt_par_iter_mut<T: Send>(x: &mut [T]) -> slice::IterMut<'_, T> {
+            x.par_iter_mut()
+        }
+
+        pub fn opt_par_range(range: Range<usize>) -> rayon::range::Iter<usize> {
+            range.into_par_iter()
+        }
+
     } else {
         use std::slice;
-        pub fn opt_par_iter<T>(x: &[T]) -> slice::Iter<T> {
+        pub fn opt_par_iter<T>(x: &[T]) -> slice::Iter<'_, T> {
             x.iter()
         }
+
+        pub fn opt_par_iter_mut<T>(x: &mut [T]) -> slice::IterMut<'_, T> {
+            x.iter_mut()
+        }
+
+        pub fn opt_par_range(range: Range<usize>) -> Range<usize> {
+            range
+        }
     }
 }
@@ -0,0 +1,500 @@
+// Detect the phases of a flight from the fixes and their timestamps: takeoff, release from the
+// tow plane, winch or the engine of a self launcher, engine runs during the flight and landing.
+//
+// All signals are calculated over a trailing time window: the ground speed, the climb rate and
+// the turn (the net change of the heading). The aircraft is flying while the ground speed is
+// above FLYING_SPEED. The launch is a continuous climb after takeoff, the release is at its top.
+// It ends when the altitude drops more than RELEASE_DROP meters below the top or when the glider
+// starts circling in a thermal right after the release. Engine runs after the release are read
+// from the engine noise level (ENL or MOP) if the fixes have one. Otherwise, they are long
+// straight climbs, in contrast to the circling in thermals. Wave and ridge lift look just the same,
+// so these runs are only uncertain and never restrict the scoring.
+
+use flat_projection::FlatPoint;
+
+use crate::error::{check_timestamps, ScoreError};
+use crate::flat::to_flat_points;
+use crate::point::{Constraints, EngineRule, Point};
+
+// The length of the trailing window in seconds
+const WINDOW: i64 = 20;
+// The minimum ground speed in km/h while flying
+const FLYING_SPEED: f32 = 40.0;
+// The altitude loss in meters after the top of the launch
+const RELEASE_DROP: i32 = 30;
+// The turn in degrees within the window while circling
+const CIRCLING_TURN: f32 = 180.0;
+// The minimum climb rate in m/s, the maximum turn in degrees within the window and the minimum
+// duration in seconds of a climb with the engine running
+const ENGINE_CLIMB: f32 = 1.0;
+const ENGINE_TURN: f32 = 90.0;
+const ENGINE_DURATION: i64 = 120;
+// The engine noise level above which the engine is running and the minimum duration in seconds
+// of an engine run, which ignores short noise e.g. while retracting the engine or on the ground
+const ENGINE_NOISE: u16 = 500;
+const ENGINE_NOISE_DURATION: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phases {
+    pub takeoff: Option<usize>,
+    pub release: Option<usize>,
+    pub landing: Option<usize>,
+    // Every engine run after the release
+    pub engine_runs: Vec<EngineRun>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EngineRun {
+    // The first and the last index of the run
+    pub start: usize,
+    pub stop: usize,
+    // Read from the engine noise level, in contrast to a straight climb without one
+    pub confirmed: bool,
+}
+
+impl Phases {
+    // Detect the phases of the flight, all points need a timestamp
+    pub fn detect<T: Point>(route: &[T]) -> Result<Phases, ScoreError> {
+        check_timestamps(route)?;
+        if route.is_empty() {
+            return Ok(Phases {
+                takeoff: None,
+                release: None,
+                landing: None,
+                engine_runs: Vec::new(),
+            });
+        }
+        let signals = Signals::new(route);
+        let takeoff = (0..route.len()).find(|&index| signals.speed[index] > FLYING_SPEED);
+        let landing = (0..route.len()).rfind(|&index| signals.speed[index] > FLYING_SPEED);
+        let release = takeoff.and_then(|takeoff| find_release(route, &signals, takeoff));
+        let engine_runs = match (release, landing) {
+            (Some(release), Some(landing)) => find_engine_runs(route, &signals, release, landing),
+            _ => Vec::new(),
+        };
+        Ok(Phases {
+            takeoff,
+            release,
+            landing,
+            engine_runs,
+        })
+    }
+
+    // Restrict the constraints to the scoring window between release and landing. The window is
+    // intersected with the start and end time of the constraints, without a detected release or
+    // landing they are kept.
+    //
+    // With EngineRule::AfterLastRun, the window starts after the last confirmed engine run above the
+    // threshold of the rule instead, and the rule is not applied to single fixes anymore: noise which
+    // is too short for an engine run, e.g. while retracting the engine, does not restart the scoring.
+    pub fn with_scoring_window<T: Point>(
+        &self,
+        route: &[T],
+        constraints: Constraints,
+    ) -> Constraints {
+        let (engine, last_run) = match (constraints.engine, self.release, self.landing) {
+            (EngineRule::AfterLastRun { threshold }, Some(release), Some(landing)) => (
+                EngineRule::Ignore,
+                find_noisy_runs(route, release, landing, threshold)
+                    .last()
+                    .map(|run| run.stop),
+            ),
+            (engine, _, _) => (engine, None),
+        };
+        let start = last_run
+            .and_then(|stop| route.get(stop + 1))
+            .or(self.release.map(|release| &route[release]));
+        Constraints {
+            start_time: start
+                .and_then(|point| point.timestamp())
+                .into_iter()
+                .chain(constraints.start_time)
+                .max(),
+            end_time: self
+                .landing
+                .and_then(|index| route[index].timestamp())
+                .into_iter()
+                .chain(constraints.end_time)
+                .min(),
+            engine,
+            ..constraints
+        }
+    }
+}
+
+// The signals of each point over the window which ends at this point
+struct Signals {
+    // The index of the first point of the window
+    window_start: Vec<usize>,
+    // km/h
+    speed: Vec<f32>,
+    // m/s
+    climb: Vec<f32>,
+    // the absolute net change of the heading in degrees
+    turn: Vec<f32>,
+}
+
+impl Signals {
+    fn new<T: Point>(route: &[T]) -> Self {
+        let flat_points: Vec<FlatPoint<f32>> = to_flat_points(route);
+        let timestamp = |index: usize| route[index].timestamp().unwrap();
+
+        // the accumulated (signed) change of the heading, which is kept while the aircraft does not move
+        let mut heading: Option<f32> = None;
+        let mut cum_turn = vec![0.0; route.len()];
+        for index in 1..route.len() {
+            cum_turn[index] = cum_turn[index - 1];
+            let (from, to) = (&flat_points[index - 1], &flat_points[index]);
+            if from.distance(to) < 0.002 {
+                continue;
+            }
+            let next = (to.x - from.x).atan2(to.y - from.y).to_degrees();
+            if let Some(heading) = heading {
+                cum_turn[index] += (next - heading + 540.0) % 360.0 - 180.0;
+            }
+            heading = Some(next);
+        }
+
+        let mut signals = Signals {
+            window_start: Vec::with_capacity(route.len()),
+            speed: Vec::with_capacity(route.len()),
+            climb: Vec::with_capacity(route.len()),
+            turn: Vec::with_capacity(route.len()),
+        };
+        let mut start = 0;
+        for index in 0..route.len() {
+            while timestamp(index) - timestamp(start) > WINDOW {
+                start += 1;
+            }
+            let duration = (timestamp(index) - timestamp(start)) as f32;
+            let (speed, climb) = if duration > 0.0 {
+                let distance = flat_distance(&flat_points, start, index);
+                let gain = route[index].altitude() as f32 - route[start].altitude() as f32;
+                (distance / duration * 3600.0, gain / duration)
+            } else {
+                (0.0, 0.0)
+            };
+            signals.window_start.push(start);
+            signals.speed.push(speed);
+            signals.climb.push(climb);
+            signals.turn.push((cum_turn[index] - cum_turn[start]).abs());
+        }
+        signals
+    }
+}
+
+fn flat_distance(flat_points: &[FlatPoint<f32>], start: usize, stop: usize) -> f32 {
+    flat_points[start].distance(&flat_points[stop])
+}
+
+// Follow the launch from the takeoff until the altitude drops below its top or circling starts
+fn find_release<T: Point>(route: &[T], signals: &Signals, takeoff: usize) -> Option<usize> {
+    let mut top = takeoff;
+    for index in takeoff..route.len() {
+        if route[index].altitude() > route[top].altitude() {
+            top = index;
+        }
+        if route[top].altitude() as i32 - route[index].altitude() as i32 > RELEASE_DROP {
+            return Some(top);
+        }
+        if signals.turn[index] > CIRCLING_TURN {
+            return Some(signals.window_start[index].max(takeoff));
+        }
+    }
+    None
+}
+
+// Find all engine runs between release and landing. With an engine noise level, these are the fixes
+// above ENGINE_NOISE, otherwise the straight climbs which last at least ENGINE_DURATION seconds.
+fn find_engine_runs<T: Point>(
+    route: &[T],
+    signals: &Signals,
+    release: usize,
+    landing: usize,
+) -> Vec<EngineRun> {
+    if route[release..=landing]
+        .iter()
+        .any(|point| point.engine_noise().is_some())
+    {
+        return find_noisy_runs(route, release, landing, ENGINE_NOISE);
+    }
+    let timestamp = |index: usize| route[index].timestamp().unwrap();
+    let mut runs = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for index in release..=landing {
+        if signals.climb[index] > ENGINE_CLIMB && signals.turn[index] < ENGINE_TURN {
+            let start = signals.window_start[index].max(release);
+            current = Some(current.map_or((start, index), |(start, _)| (start, index)));
+        } else if let Some((start, stop)) = current.take() {
+            runs.push((start, stop));
+        }
+    }
+    runs.extend(current);
+    runs.into_iter()
+        .filter(|&(start, stop)| timestamp(stop) - timestamp(start) >= ENGINE_DURATION)
+        .map(|(start, stop)| EngineRun {
+            start,
+            stop,
+            confirmed: false,
+        })
+        .collect()
+}
+
+// Find all consecutive fixes between release and landing with an engine noise level above
+// threshold which last at least ENGINE_NOISE_DURATION seconds
+fn find_noisy_runs<T: Point>(
+    route: &[T],
+    release: usize,
+    landing: usize,
+    threshold: u16,
+) -> Vec<EngineRun> {
+    let timestamp = |index: usize| route[index].timestamp().unwrap();
+    let mut runs = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (index, point) in route.iter().enumerate().take(landing + 1).skip(release) {
+        if point.engine_noise().is_some_and(|noise| noise > threshold) {
+            current = Some(current.map_or((index, index), |(start, _)| (start, index)));
+        } else if let Some(run) = current.take() {
+            runs.push(run);
+        }
+    }
+    runs.extend(current);
+    runs.into_iter()
+        .filter(|&(start, stop)| timestamp(stop) - timestamp(start) >= ENGINE_NOISE_DURATION)
+        .map(|(start, stop)| EngineRun {
+            start,
+            stop,
+            confirmed: true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flight::{AltitudePolicy, Flight};
+    use crate::point::PointImpl;
+
+    fn detect(file: &str) -> (Vec<crate::flight::Fix>, Phases) {
+        let fixes = Flight::parse(file, AltitudePolicy::Pressure).unwrap().fixes;
+        let phases = Phases::detect(&fixes).unwrap();
+        (fixes, phases)
+    }
+
+    #[test]
+    fn aerotow() {
+        let (fixes, phases) = detect(include_str!("../fixtures/2023-06-17_288167.igc"));
+        let release = fixes[phases.release.unwrap()].timestamp;
+        assert!((release - (8 * 3600 + 12 * 60 + 29)).abs() <= 5);
+        assert!(phases.takeoff.unwrap() < phases.release.unwrap());
+        assert!(fixes.len() - phases.landing.unwrap() < 30);
+        assert!(phases.engine_runs.is_empty());
+    }
+
+    #[test]
+    fn self_launch() {
+        let (fixes, phases) = detect(include_str!("../fixtures/schunk_1000m.igc"));
+        let release = fixes[phases.release.unwrap()].timestamp;
+        assert!((release - (8 * 3600 + 16 * 60 + 30)).abs() <= 5);
+        assert!(phases.engine_runs.is_empty());
+    }
+
+    #[test]
+    fn scoring_window_uses_release_and_landing() {
+        let (fixes, phases) = detect(include_str!("../fixtures/2023-06-17_288167.igc"));
+        let constraints = phases.with_scoring_window(&fixes, Constraints::default());
+        assert_eq!(
+            constraints.start_time,
+            Some(fixes[phases.release.unwrap()].timestamp)
+        );
+        assert_eq!(
+            constraints.end_time,
+            Some(fixes[phases.landing.unwrap()].timestamp)
+        );
+    }
+
+    #[test]
+    fn scoring_window_is_intersected_with_the_constraints() {
+        let (fixes, phases) = detect(include_str!("../fixtures/2023-06-17_288167.igc"));
+        let release = fixes[phases.release.unwrap()].timestamp;
+        let landing = fixes[phases.landing.unwrap()].timestamp;
+        // a narrower window of the caller is kept
+        let narrow = Constraints {
+            start_time: Some(release + 600),
+            end_time: Some(landing - 600),
+            ..Default::default()
+        };
+        assert_eq!(phases.with_scoring_window(&fixes, narrow), narrow);
+        // a wider one is restricted to release and landing
+        let wide = Constraints {
+            start_time: Some(release - 600),
+            end_time: Some(landing + 600),
+            ..Default::default()
+        };
+        let constraints = phases.with_scoring_window(&fixes, wide);
+        assert_eq!(
+            (constraints.start_time, constraints.end_time),
+            (Some(release), Some(landing))
+        );
+    }
+
+    #[test]
+    fn scoring_window_keeps_constraints_without_phases() {
+        let route = vec![
+            PointImpl {
+                latitude: 50.0,
+                longitude: 10.0,
+                altitude: 0,
+                timestamp: Some(0),
+                engine_noise: None,
+            };
+            10
+        ];
+        let phases = Phases::detect(&route).unwrap();
+        let constraints = Constraints {
+            start_time: Some(2),
+            end_time: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(phases.with_scoring_window(&route, constraints), constraints);
+    }
+
+    #[test]
+    fn winch_launch() {
+        // accelerate on the ground for 10 seconds to 100 km/h, climb with 12 m/s for 30 seconds,
+        // push over and glide with 1 m/s
+        let route: Vec<_> = (0..300)
+            .map(|second| {
+                let distance = match second {
+                    0..=9 => 5.0 * (second * second) as f64,
+                    _ => 500.0 + 100.0 * (second - 10) as f64,
+                } / 3600.0;
+                let altitude = match second {
+                    0..=9 => 0,
+                    10..=39 => 12 * (second - 10),
+                    _ => 348 - (second - 39),
+                };
+                PointImpl {
+                    latitude: 50.0 + distance / 111.2,
+                    longitude: 10.0,
+                    altitude: altitude as i16,
+                    timestamp: Some(second as i64),
+                    engine_noise: None,
+                }
+            })
+            .collect();
+        let phases = Phases::detect(&route).unwrap();
+        assert!(phases.takeoff.unwrap() <= 10);
+        assert_eq!(phases.release, Some(39));
+        assert!(phases.engine_runs.is_empty());
+    }
+
+    #[test]
+    fn straight_climb_is_an_uncertain_engine_run() {
+        // glide north with 100 km/h, climb for 5 minutes with 2 m/s, glide again
+        let route: Vec<_> = (0..900)
+            .map(|second| {
+                let altitude = match second {
+                    0..=299 => 1000 - second,
+                    300..=599 => 700 + 2 * (second - 300),
+                    _ => 1300 - (second - 600),
+                };
+                PointImpl {
+                    latitude: 50.0 + second as f64 * 100.0 / 3600.0 / 111.2,
+                    longitude: 10.0,
+                    altitude: altitude as i16,
+                    timestamp: Some(second as i64),
+                    engine_noise: None,
+                }
+            })
+            .collect();
+        let phases = Phases::detect(&route).unwrap();
+        assert_eq!(phases.takeoff, Some(1));
+        assert_eq!(phases.release, Some(1));
+        assert_eq!(phases.engine_runs.len(), 1);
+        let run = phases.engine_runs[0];
+        assert!((280..=300).contains(&run.start) && (600..=620).contains(&run.stop));
+        assert!(!run.confirmed);
+
+        // the climb might be wave lift, so it does not restrict the scoring
+        let constraints = Constraints {
+            engine: EngineRule::AfterLastRun { threshold: 500 },
+            ..Default::default()
+        };
+        let constraints = phases.with_scoring_window(&route, constraints);
+        assert_eq!(constraints.start_time, Some(1));
+    }
+
+    #[test]
+    fn engine_runs_use_the_noise_level() {
+        // glide north with 100 km/h, run the engine for a minute without climbing and
+        // make some noise for a few seconds
+        let route: Vec<_> = (0..600)
+            .map(|second| PointImpl {
+                latitude: 50.0 + second as f64 * 100.0 / 3600.0 / 111.2,
+                longitude: 10.0,
+                altitude: (1000 - second) as i16,
+                timestamp: Some(second as i64),
+                engine_noise: Some(match second {
+                    300..=359 | 400..=409 => 900,
+                    _ => 50,
+                }),
+            })
+            .collect();
+        let phases = Phases::detect(&route).unwrap();
+        assert_eq!(
+            phases.engine_runs,
+            vec![EngineRun {
+                start: 300,
+                stop: 359,
+                confirmed: true
+            }]
+        );
+
+        // scoring starts after the run, the short noise afterwards is ignored
+        let constraints = Constraints {
+            engine: EngineRule::AfterLastRun { threshold: 500 },
+            ..Default::default()
+        };
+        let constraints = phases.with_scoring_window(&route, constraints);
+        assert_eq!(constraints.start_time, Some(360));
+        assert_eq!(constraints.engine, EngineRule::Ignore);
+        let result =
+            crate::free::optimize(&route, 0.0_f32, 2, constraints, &crate::distance::Vincenty)
+                .unwrap();
+        assert!(result.path[0] >= 360);
+
+        // other rules are kept
+        let constraints = EngineRule::NoTurnpoints { threshold: 500 };
+        let window = phases.with_scoring_window(
+            &route,
+            Constraints {
+                engine: constraints,
+                ..Default::default()
+            },
+        );
+        assert_eq!(window.engine, constraints);
+        assert_eq!(
+            window.start_time,
+            Some(route[phases.release.unwrap()].timestamp.unwrap())
+        );
+    }
+
+    #[test]
+    fn no_flight_without_movement() {
+        let route = vec![
+            PointImpl {
+                latitude: 50.0,
+                longitude: 10.0,
+                altitude: 0,
+                timestamp: Some(0),
+                engine_noise: None,
+            };
+            10
+        ];
+        let phases = Phases::detect(&route).unwrap();
+        assert_eq!(phases.takeoff, None);
+        assert_eq!(phases.release, None);
+        assert_eq!(phases.landing, None);
+    }
+}
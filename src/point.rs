@@ -1,49 +1,248 @@
-use crate::vincenty::vincenty_distance;
+use crate::distance::DistanceModel;
+use crate::error::{check_timestamps, ScoreError};
+use crate::float::Float;
 use flat_projection::FlatPoint;
+use std::ops::Range;
 pub trait Point: Sync {
-    fn latitude(&self) -> f32;
-    fn longitude(&self) -> f32;
+    fn latitude(&self) -> f64;
+    fn longitude(&self) -> f64;
     fn altitude(&self) -> i16;
+    // Seconds since an arbitrary (but fixed) epoch, e.g. seconds since midnight of the flight
+    fn timestamp(&self) -> Option<i64> {
+        None
+    }
+    // The engine noise level (e.g. ENL or MOP of an IGC file)
+    fn engine_noise(&self) -> Option<u16> {
+        None
+    }
+}
+impl<T: Point> Point for &T {
+    fn latitude(&self) -> f64 {
+        (*self).latitude()
+    }
+    fn longitude(&self) -> f64 {
+        (*self).longitude()
+    }
+    fn altitude(&self) -> i16 {
+        (*self).altitude()
+    }
+    fn timestamp(&self) -> Option<i64> {
+        (*self).timestamp()
+    }
+    fn engine_noise(&self) -> Option<u16> {
+        (*self).engine_noise()
+    }
 }
+
+// Round an altitude in meters to the i16 of Point::altitude.
+// Return None if it is not finite or out of range, instead of silently wrapping it around.
+pub fn to_altitude(meters: f64) -> Option<i16> {
+    let meters = meters.round();
+    (meters >= f64::from(i16::MIN) && meters <= f64::from(i16::MAX)).then_some(meters as i16)
+}
+
 #[derive(Clone)]
 pub struct PointImpl {
-    pub latitude: f32,
-    pub longitude: f32,
+    pub latitude: f64,
+    pub longitude: f64,
     pub altitude: i16,
+    pub timestamp: Option<i64>,
+    pub engine_noise: Option<u16>,
 }
 
 impl Point for PointImpl {
-    fn latitude(&self) -> f32 {
+    fn latitude(&self) -> f64 {
         self.latitude
     }
-    fn longitude(&self) -> f32 {
+    fn longitude(&self) -> f64 {
         self.longitude
     }
     fn altitude(&self) -> i16 {
         self.altitude
     }
+    fn timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+    fn engine_noise(&self) -> Option<u16> {
+        self.engine_noise
+    }
+}
+
+// The maximum altitude loss between the start and the finish of a route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AltitudeRule {
+    // The finish must not be more than this many meters below the start
+    Fixed(i32),
+    // The loss must not exceed this ratio of the scored distance, e.g. 0.01 for 1%
+    Relative(f64),
+    Disabled,
+}
+
+impl Default for AltitudeRule {
+    fn default() -> Self {
+        AltitudeRule::Fixed(1000)
+    }
+}
+
+impl AltitudeRule {
+    // Return if an altitude loss in meters is allowed for a route with the given distance in km.
+    // The allowed loss never decreases with the distance, so checking against an upper bound of
+    // the distance rules out all routes that can not be valid.
+    pub fn allows<F: Float>(&self, loss: i32, distance: F) -> bool {
+        match *self {
+            AltitudeRule::Fixed(max_loss) => loss <= max_loss,
+            AltitudeRule::Relative(ratio) => {
+                F::from_f64(loss.into()) <= F::from_f64(ratio) * distance * F::from_f64(1000.0)
+            }
+            AltitudeRule::Disabled => true,
+        }
+    }
+}
+
+// How fixes with a running engine, i.e. an engine noise level above the threshold, are treated.
+// Fixes without an engine noise level are treated as fixes without a running engine.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EngineRule {
+    #[default]
+    Ignore,
+    // No turnpoint (including the first and the last one) may be placed on a fix with a running engine.
+    // Legs between two turnpoints may still contain an engine run.
+    NoTurnpoints {
+        threshold: u16,
+    },
+    // Only the fixes after the last engine run are scored
+    AfterLastRun {
+        threshold: u16,
+    },
+}
+
+impl EngineRule {
+    // Return if a turnpoint may be placed on the point
+    pub fn allows_turnpoint<T: Point>(&self, point: &T) -> bool {
+        match *self {
+            EngineRule::NoTurnpoints { threshold } => !engine_on(point, threshold),
+            EngineRule::Ignore | EngineRule::AfterLastRun { .. } => true,
+        }
+    }
+
+    // Return the index of the last fix with a running engine
+    pub fn last_run<T: Point>(&self, route: &[T]) -> Option<usize> {
+        match *self {
+            EngineRule::AfterLastRun { threshold } => {
+                route.iter().rposition(|point| engine_on(point, threshold))
+            }
+            EngineRule::Ignore | EngineRule::NoTurnpoints { .. } => None,
+        }
+    }
+}
+
+fn engine_on<T: Point>(point: &T, threshold: u16) -> bool {
+    point.engine_noise().is_some_and(|noise| noise > threshold)
+}
+
+// The constraints a route has to comply with
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Constraints {
+    // The maximum altitude loss between the first and the last turnpoint
+    pub altitude: AltitudeRule,
+    // Only fixes with a timestamp at or after start_time (e.g. the release) are scored
+    pub start_time: Option<i64>,
+    // Only fixes with a timestamp at or before end_time (e.g. the landing) are scored
+    pub end_time: Option<i64>,
+    // The maximum time in seconds between the first and the last turnpoint
+    pub max_duration: Option<i64>,
+    // How fixes with a running engine are treated
+    pub engine: EngineRule,
+}
+
+impl From<AltitudeRule> for Constraints {
+    fn from(altitude: AltitudeRule) -> Self {
+        Constraints {
+            altitude,
+            ..Default::default()
+        }
+    }
+}
+
+impl Constraints {
+    // Return if the constraints depend on the timestamps of the fixes
+    pub fn uses_time(&self) -> bool {
+        self.start_time.is_some() || self.end_time.is_some() || self.max_duration.is_some()
+    }
+
+    // Return the range of fixes which can be scored: the fixes within the time window and after the
+    // last engine run, if scoring restarts after it. Without these constraints, this is the whole route.
+    pub fn scoring_window<T: Point>(&self, route: &[T]) -> Result<Range<usize>, ScoreError> {
+        let mut start = 0;
+        let mut stop = route.len();
+        if self.uses_time() {
+            check_timestamps(route)?;
+            let timestamp = |point: &T| point.timestamp().unwrap();
+            if let Some(start_time) = self.start_time {
+                start = route
+                    .iter()
+                    .position(|point| timestamp(point) >= start_time)
+                    .unwrap_or(route.len());
+            }
+            if let Some(end_time) = self.end_time {
+                stop = route
+                    .iter()
+                    .rposition(|point| timestamp(point) <= end_time)
+                    .map_or(0, |index| index + 1);
+            }
+        }
+        if let Some(last_run) = self.engine.last_run(&route[..stop]) {
+            start = start.max(last_run + 1);
+        }
+        Ok(start..stop.max(start))
+    }
+
+    // Return if a route from start to stop with the given timestamps is allowed.
+    // Missing timestamps are not restricted.
+    fn allows_duration(&self, start: Option<i64>, stop: Option<i64>) -> bool {
+        match (self.max_duration, start, stop) {
+            (Some(max_duration), Some(start), Some(stop)) => stop - start <= max_duration,
+            _ => true,
+        }
+    }
 }
 
 pub trait Valid {
-    fn valid(&self, start: usize, stop: usize) -> bool;
+    fn valid<F: Float>(
+        &self,
+        start: usize,
+        stop: usize,
+        constraints: Constraints,
+        distance: F,
+    ) -> bool;
 }
 
 impl<T: Point> Valid for [T] {
-    fn valid(&self, start: usize, stop: usize) -> bool {
-        self[start].altitude() - self[stop].altitude() <= 1000
+    fn valid<F: Float>(
+        &self,
+        start: usize,
+        stop: usize,
+        constraints: Constraints,
+        distance: F,
+    ) -> bool {
+        let loss = self[start].altitude() as i32 - self[stop].altitude() as i32;
+        constraints.altitude.allows(loss, distance)
+            && constraints.allows_duration(self[start].timestamp(), self[stop].timestamp())
+            && constraints.engine.allows_turnpoint(&self[start])
+            && constraints.engine.allows_turnpoint(&self[stop])
     }
 }
 
-pub trait ApproxDistance {
-    fn distance(&self, start: usize, stop: usize) -> f32;
-    fn cum_distance(&self, path: &Path) -> f32;
+pub trait ApproxDistance<F> {
+    fn distance(&self, start: usize, stop: usize) -> F;
+    fn cum_distance(&self, path: &Path) -> F;
 }
 
-impl ApproxDistance for [FlatPoint<f32>] {
-    fn distance(&self, start: usize, stop: usize) -> f32 {
+impl<F: Float> ApproxDistance<F> for [FlatPoint<F>] {
+    fn distance(&self, start: usize, stop: usize) -> F {
         self[start].distance(&self[stop])
     }
-    fn cum_distance(&self, path: &Path) -> f32 {
+    fn cum_distance(&self, path: &Path) -> F {
         path.iter()
             .zip(path.iter().skip(1))
             .map(|(i1, i2)| self.distance(*i1, *i2))
@@ -53,16 +252,18 @@ impl ApproxDistance for [FlatPoint<f32>] {
 
 pub type Path = Vec<usize>;
 
-pub trait VincentyDistance {
-    fn cum_distance(&self, path: &Path) -> f32;
+pub trait ModelDistance {
+    // Return the sum of the distances along the path in the distance model,
+    // None if one of them does not converge
+    fn cum_distance<F: Float, M: DistanceModel>(&self, path: &Path, model: &M) -> Option<F>;
 }
 
-impl<T: Point> VincentyDistance for &[T] {
-    fn cum_distance(&self, path: &Path) -> f32 {
+impl<T: Point> ModelDistance for &[T] {
+    fn cum_distance<F: Float, M: DistanceModel>(&self, path: &Path, model: &M) -> Option<F> {
         path.iter()
             .zip(path.iter().skip(1))
-            .map(|(i1, i2)| (&self[*i1], &self[*i2]))
-            .map(|(fix1, fix2)| vincenty_distance(fix1, fix2))
+            .map(|(i1, i2)| model.inverse(&self[*i1], &self[*i2]))
+            .map(|inverse| inverse.map(|(distance, _)| distance))
             .sum()
     }
 }
@@ -72,55 +273,129 @@ mod tests {
     use assert_approx_eq::assert_approx_eq;
 
     use super::*;
+    use crate::distance::Vincenty;
     #[test]
     fn route_valid_for_negative_alt_diff() {
-        let points = vec![
+        let points = [
             PointImpl {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: -1000,
+                timestamp: None,
+                engine_noise: None,
             },
             PointImpl {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
         ];
-        assert!(points.valid(0, 1));
+        assert!(points.valid(0, 1, Constraints::default(), 0.0));
     }
 
     #[test]
     fn route_not_valid_for_high_alt_diff() {
-        let points = vec![
+        let points = [
             PointImpl {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
             PointImpl {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: 2_000,
+                timestamp: None,
+                engine_noise: None,
+            },
+        ];
+        assert!(points.valid(0, 1, Constraints::default(), 0.0));
+    }
+
+    #[test]
+    fn altitude_rules() {
+        let points = [
+            PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude: 1_500,
+                timestamp: None,
+                engine_noise: None,
+            },
+            PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude: 0,
+                timestamp: None,
+                engine_noise: None,
+            },
+        ];
+        assert!(!points.valid(0, 1, AltitudeRule::Fixed(1000).into(), 0.0));
+        assert!(points.valid(0, 1, AltitudeRule::Fixed(1500).into(), 0.0));
+        assert!(!points.valid(0, 1, AltitudeRule::Relative(0.01).into(), 100.0));
+        assert!(points.valid(0, 1, AltitudeRule::Relative(0.01).into(), 150.0));
+        assert!(points.valid(0, 1, AltitudeRule::Disabled.into(), 0.0));
+    }
+
+    #[test]
+    fn max_duration() {
+        let points = [
+            PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude: 0,
+                timestamp: Some(1_000),
+                engine_noise: None,
+            },
+            PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude: 0,
+                timestamp: Some(4_600),
+                engine_noise: None,
             },
         ];
-        assert!(points.valid(0, 1));
+        let constraints = |max_duration| Constraints {
+            max_duration,
+            ..Default::default()
+        };
+        assert!(points.valid(0, 1, constraints(None), 0.0));
+        assert!(points.valid(0, 1, constraints(Some(3_600)), 0.0));
+        assert!(!points.valid(0, 1, constraints(Some(3_599)), 0.0));
+    }
+
+    #[test]
+    fn altitude_out_of_range_is_rejected() {
+        assert_eq!(to_altitude(1234.6), Some(1235));
+        assert_eq!(to_altitude(-32768.0), Some(i16::MIN));
+        assert_eq!(to_altitude(32767.4), Some(i16::MAX));
+        assert_eq!(to_altitude(32767.5), None);
+        assert_eq!(to_altitude(-40000.0), None);
+        assert_eq!(to_altitude(f64::NAN), None);
+        assert_eq!(to_altitude(f64::INFINITY), None);
     }
 
     #[test]
     fn approx_distance_between_two_points() {
-        let points = vec![FlatPoint { x: 0.0, y: 0.0 }, FlatPoint { x: 1.0, y: 1.0 }];
+        let points: [FlatPoint<f32>; 2] =
+            [FlatPoint { x: 0.0, y: 0.0 }, FlatPoint { x: 1.0, y: 1.0 }];
         assert_approx_eq!(points.distance(0, 1), 1.41, 0.01);
     }
 
     #[test]
     fn approx_distance_same_points_is_zero() {
-        let points = vec![FlatPoint { x: 0.0, y: 0.0 }, FlatPoint { x: 1.0, y: 1.0 }];
+        let points: [FlatPoint<f32>; 2] =
+            [FlatPoint { x: 0.0, y: 0.0 }, FlatPoint { x: 1.0, y: 1.0 }];
         assert_eq!(points.distance(0, 0), 0.0);
     }
 
     #[test]
     fn approx_cum_distance_adds_up() {
-        let points = vec![
+        let points: [FlatPoint<f32>; 3] = [
             FlatPoint { x: 0.0, y: 0.0 },
             FlatPoint { x: 0.0, y: 1.0 },
             FlatPoint { x: 3.0, y: 5.0 },
@@ -131,24 +406,37 @@ mod tests {
 
     #[test]
     fn cumulative_vincenty_distance_adds_up() {
-        let points = vec![
+        let points = [
             PointImpl {
                 latitude: 50.0,
                 longitude: 10.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
             PointImpl {
                 latitude: 51.0,
                 longitude: 11.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
             PointImpl {
                 latitude: 52.0,
                 longitude: 12.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
         ];
         let path = vec![0, 1, 2];
-        assert_approx_eq!(&points.as_slice().cum_distance(&path), 263.08, 0.01);
+        assert_approx_eq!(
+            &points
+                .as_slice()
+                .cum_distance::<f32, _>(&path, &Vincenty)
+                .unwrap(),
+            263.08,
+            0.01
+        );
     }
 }
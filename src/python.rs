@@ -0,0 +1,778 @@
+// Python bindings, built with maturin and the python feature
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use numpy::{PyArray1, PyReadonlyArray1};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use crate::float::Float;
+use crate::parallel::*;
+use crate::{distance, error, export, flight, free, point, result};
+
+impl From<error::ScoreError> for PyErr {
+    fn from(err: error::ScoreError) -> PyErr {
+        match err {
+            error::ScoreError::NoValidSolution
+            | error::ScoreError::BudgetExhausted
+            | error::ScoreError::Cancelled
+            | error::ScoreError::NoConvergence { .. } => PyRuntimeError::new_err(err.to_string()),
+            _ => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+impl From<error::IgcError> for PyErr {
+    fn from(err: error::IgcError) -> PyErr {
+        match err {
+            error::IgcError::Io(err) => err.into(),
+            _ => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+// A leg of the optimized route, see result::Leg
+#[pyclass(name = "Leg", get_all)]
+#[derive(Clone)]
+struct PyLeg {
+    start: usize,
+    stop: usize,
+    distance: f64,
+    bearing: f64,
+    start_altitude: i16,
+    stop_altitude: i16,
+}
+
+impl<F: Float> From<result::Leg<F>> for PyLeg {
+    fn from(leg: result::Leg<F>) -> Self {
+        PyLeg {
+            start: leg.start,
+            stop: leg.stop,
+            distance: leg.distance.to_f64().unwrap(),
+            bearing: leg.bearing.to_f64().unwrap(),
+            start_altitude: leg.start_altitude,
+            stop_altitude: leg.stop_altitude,
+        }
+    }
+}
+
+// The state of the search, see free::Progress
+#[pyclass(name = "Progress", get_all)]
+#[derive(Clone)]
+struct PyProgress {
+    remaining: usize,
+    best_distance: f64,
+    checked: usize,
+    pruned: usize,
+    built: usize,
+    hits: usize,
+    misses: usize,
+    scanned: usize,
+}
+
+impl<F: Float> From<&free::Progress<F>> for PyProgress {
+    fn from(progress: &free::Progress<F>) -> Self {
+        PyProgress {
+            remaining: progress.remaining,
+            best_distance: progress.best_distance.to_f64().unwrap(),
+            checked: progress.checked,
+            pruned: progress.pruned,
+            built: progress.built,
+            hits: progress.cache.hits,
+            misses: progress.cache.misses,
+            scanned: progress.cache.scanned,
+        }
+    }
+}
+
+// A flight read from an IGC file, see flight::Flight. The task is a list of
+// (latitude, longitude, name) tuples, missing ENL or MOP values are NaN.
+#[pyclass(name = "Flight", get_all)]
+struct PyFlight {
+    longitude: Py<PyArray1<f64>>,
+    latitude: Py<PyArray1<f64>>,
+    altitude: Py<PyArray1<i64>>,
+    time: Py<PyArray1<i64>>,
+    enl: Option<Py<PyArray1<f64>>>,
+    mop: Option<Py<PyArray1<f64>>>,
+    headers: HashMap<String, String>,
+    task_name: Option<String>,
+    task: Option<Vec<(f64, f64, Option<String>)>>,
+}
+
+impl PyFlight {
+    fn new(py: Python, flight: flight::Flight) -> Self {
+        let fixes = &flight.fixes;
+        let extension = |mnemonic: &str, value: fn(&flight::Fix) -> Option<u16>| {
+            flight.extensions.iter().any(|e| e == mnemonic).then(|| {
+                let values = fixes
+                    .iter()
+                    .map(|fix| value(fix).map_or(f64::NAN, f64::from));
+                PyArray1::from_iter(py, values).to_owned()
+            })
+        };
+        PyFlight {
+            longitude: PyArray1::from_iter(py, fixes.iter().map(|fix| fix.longitude)).to_owned(),
+            latitude: PyArray1::from_iter(py, fixes.iter().map(|fix| fix.latitude)).to_owned(),
+            altitude: PyArray1::from_iter(py, fixes.iter().map(|fix| fix.altitude as i64))
+                .to_owned(),
+            time: PyArray1::from_iter(py, fixes.iter().map(|fix| fix.timestamp)).to_owned(),
+            enl: extension("ENL", |fix| fix.enl),
+            mop: extension("MOP", |fix| fix.mop),
+            task_name: flight.task.as_ref().and_then(|task| task.name.clone()),
+            task: flight.task.as_ref().map(|task| {
+                task.turnpoints
+                    .iter()
+                    .map(|tp| (tp.latitude, tp.longitude, tp.name.clone()))
+                    .collect()
+            }),
+            headers: flight.headers,
+        }
+    }
+}
+
+// The altitudes in meters, as an integer or a float array
+#[derive(FromPyObject)]
+enum PyAltitudes<'py> {
+    Int(PyReadonlyArray1<'py, i64>),
+    Float(PyReadonlyArray1<'py, f64>),
+}
+
+impl PyAltitudes<'_> {
+    // Float altitudes are rounded, altitudes out of the range of Point::altitude (i16) are rejected
+    fn to_meters(&self) -> PyResult<Vec<i16>> {
+        let out_of_range = |index| {
+            PyValueError::new_err(format!(
+                "the altitude at index {} is not a number between -32768 and 32767 m",
+                index
+            ))
+        };
+        match self {
+            PyAltitudes::Int(alt) => alt
+                .as_slice()?
+                .iter()
+                .enumerate()
+                .map(|(index, &meters)| i16::try_from(meters).map_err(|_| out_of_range(index)))
+                .collect(),
+            PyAltitudes::Float(alt) => alt
+                .as_slice()?
+                .iter()
+                .enumerate()
+                .map(|(index, &meters)| {
+                    point::to_altitude(meters).ok_or_else(|| out_of_range(index))
+                })
+                .collect(),
+        }
+    }
+}
+
+// Build the points from the numpy arrays, which all need the same length.
+// Missing engine noise values (NaN) are treated as fixes without an engine noise level.
+fn to_points(
+    longitude: &PyReadonlyArray1<f64>,
+    latitude: &PyReadonlyArray1<f64>,
+    alt: &PyAltitudes,
+    time: Option<PyReadonlyArray1<i64>>,
+    engine_noise: Option<PyReadonlyArray1<f64>>,
+) -> PyResult<Vec<point::PointImpl>> {
+    let longitude = longitude.as_slice()?;
+    let latitude = latitude.as_slice()?;
+    let alt = alt.to_meters()?;
+    let time = time.as_ref().map(|time| time.as_slice()).transpose()?;
+    let engine_noise = engine_noise
+        .as_ref()
+        .map(|noise| noise.as_slice())
+        .transpose()?;
+    if latitude.len() != longitude.len()
+        || alt.len() != longitude.len()
+        || time.is_some_and(|time| time.len() != longitude.len())
+        || engine_noise.is_some_and(|noise| noise.len() != longitude.len())
+    {
+        return Err(PyValueError::new_err(
+            "longitude, latitude, altitude, time and engine_noise must have the same length",
+        ));
+    }
+    let engine_noise = engine_noise.map(to_noise_levels).transpose()?;
+    Ok((0..longitude.len())
+        .map(|i| point::PointImpl {
+            longitude: longitude[i],
+            latitude: latitude[i],
+            altitude: alt[i],
+            timestamp: time.map(|time| time[i]),
+            engine_noise: engine_noise.as_ref().and_then(|noise| noise[i]),
+        })
+        .collect())
+}
+
+// Round the engine noise levels to the u16 of Point::engine_noise. NaN is a missing value, other
+// values which are not finite or out of range raise instead of being saturated.
+fn to_noise_levels(noise: &[f64]) -> PyResult<Vec<Option<u16>>> {
+    noise
+        .iter()
+        .enumerate()
+        .map(|(index, &level)| {
+            if level.is_nan() {
+                return Ok(None);
+            }
+            let level = level.round();
+            if level >= 0.0 && level <= f64::from(u16::MAX) {
+                Ok(Some(level as u16))
+            } else {
+                Err(PyValueError::new_err(format!(
+                    "the engine noise at index {} is not a number between 0 and 65535",
+                    index
+                )))
+            }
+        })
+        .collect()
+}
+
+// The result for a path of a previous optimization, e.g. to export it. The legs are measured with
+// the distance model of the optimization.
+fn to_result(
+    path: Vec<usize>,
+    points: &[point::PointImpl],
+    model: PyDistanceModel,
+) -> PyResult<result::OptimizationResult> {
+    if path.is_empty() || path.iter().any(|&index| index >= points.len()) {
+        return Err(PyValueError::new_err(
+            "path must contain indices of the points",
+        ));
+    }
+    Ok(result::OptimizationResult::new(
+        path,
+        points,
+        &model.for_points(points),
+    )?)
+}
+
+// The distance models which can be selected by name
+#[derive(Clone, Copy)]
+enum PyDistanceModel {
+    Vincenty,
+    Karney,
+    FaiSphere,
+    Flat,
+}
+
+impl PyDistanceModel {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "vincenty" => Ok(PyDistanceModel::Vincenty),
+            "karney" => Ok(PyDistanceModel::Karney),
+            "fai_sphere" => Ok(PyDistanceModel::FaiSphere),
+            "flat" => Ok(PyDistanceModel::Flat),
+            _ => Err(PyValueError::new_err(
+                "distance_model must be one of 'vincenty', 'karney', 'fai_sphere' or 'flat'",
+            )),
+        }
+    }
+
+    // The model for the points of a flight, the flat model uses the projection of the points
+    fn for_points(self, points: &[point::PointImpl]) -> FlightModel {
+        match self {
+            PyDistanceModel::Vincenty => FlightModel::Vincenty,
+            PyDistanceModel::Karney => FlightModel::Karney,
+            PyDistanceModel::FaiSphere => FlightModel::FaiSphere,
+            PyDistanceModel::Flat => FlightModel::Flat(distance::Flat::new(points)),
+        }
+    }
+}
+
+// The distance model of a flight, which the free distance uses for the sliding of the first and
+// the last turnpoint and to measure the legs of the result. The optimizations measure with Vincenty
+// on WGS-84 by default, record and badge claims use the FAI sphere.
+enum FlightModel {
+    Vincenty,
+    Karney,
+    FaiSphere,
+    Flat(distance::Flat),
+}
+
+impl distance::DistanceModel for FlightModel {
+    fn inverse<F: Float, T: point::Point>(&self, fix1: &T, fix2: &T) -> Option<(F, F)> {
+        match self {
+            FlightModel::Vincenty => distance::Vincenty.inverse(fix1, fix2),
+            FlightModel::Karney => distance::Karney.inverse(fix1, fix2),
+            FlightModel::FaiSphere => distance::FaiSphere.inverse(fix1, fix2),
+            FlightModel::Flat(flat) => flat.inverse(fix1, fix2),
+        }
+    }
+}
+
+// The signal handlers and the progress callback need the GIL, so they are only called this often
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// The settings of the free distance which are shared by optimize and optimize_many
+#[derive(Clone, Copy)]
+struct Settings {
+    legs: usize,
+    break_at: f64,
+    constraints: point::Constraints,
+    budget: free::Budget,
+    model: PyDistanceModel,
+}
+
+// Run the free distance optimization without holding the GIL. A KeyboardInterrupt (or any other
+// exception of a signal handler or the progress callback) cancels the optimization and is raised.
+// If the callback returns False, the best valid result so far is returned.
+fn optimize_without_gil<F: Float>(
+    py: Python,
+    points: &[point::PointImpl],
+    settings: Settings,
+    progress: Option<&PyObject>,
+) -> PyResult<result::AnytimeResult<F>> {
+    let mut raised = None;
+    let mut last_check = Instant::now();
+    let result = py.allow_threads(|| {
+        free::optimize_with_progress(
+            points,
+            F::from_f64(settings.break_at),
+            settings.legs,
+            settings.constraints,
+            &settings.model.for_points(points),
+            settings.budget,
+            |state| {
+                if last_check.elapsed() < CHECK_INTERVAL {
+                    return true;
+                }
+                last_check = Instant::now();
+                Python::with_gil(|py| {
+                    py.check_signals()?;
+                    match progress {
+                        Some(callback) => {
+                            let proceed = callback.call1(py, (PyProgress::from(state),))?;
+                            Ok(!matches!(proceed.extract::<bool>(py), Ok(false)))
+                        }
+                        None => Ok(true),
+                    }
+                })
+                .unwrap_or_else(|err: PyErr| {
+                    raised = Some(err);
+                    false
+                })
+            },
+        )
+    });
+    if let Some(err) = raised {
+        return Err(err);
+    }
+    Ok(result?)
+}
+
+// A flight of optimize_many: (longitude, latitude, alt) or (longitude, latitude, alt, time)
+#[derive(FromPyObject)]
+enum PyTrack<'py> {
+    WithTime(
+        PyReadonlyArray1<'py, f64>,
+        PyReadonlyArray1<'py, f64>,
+        PyAltitudes<'py>,
+        PyReadonlyArray1<'py, i64>,
+    ),
+    WithoutTime(
+        PyReadonlyArray1<'py, f64>,
+        PyReadonlyArray1<'py, f64>,
+        PyAltitudes<'py>,
+    ),
+}
+
+impl<'py> PyTrack<'py> {
+    fn to_points(
+        &self,
+        engine_noise: Option<PyReadonlyArray1<'py, f64>>,
+    ) -> PyResult<Vec<point::PointImpl>> {
+        match self {
+            PyTrack::WithTime(longitude, latitude, alt, time) => {
+                to_points(longitude, latitude, alt, Some(time.clone()), engine_noise)
+            }
+            PyTrack::WithoutTime(longitude, latitude, alt) => {
+                to_points(longitude, latitude, alt, None, engine_noise)
+            }
+        }
+    }
+}
+
+// Optimize all flights with their settings in parallel on the rayon pool without holding the GIL.
+// The errors are returned for each flight, so one invalid flight does not stop the others.
+fn optimize_all<F: Float>(
+    py: Python,
+    flights: &[(Vec<point::PointImpl>, Settings)],
+) -> Vec<Result<result::AnytimeResult<F>, error::ScoreError>> {
+    py.allow_threads(|| {
+        opt_par_iter(flights)
+            .map(|(points, settings)| {
+                free::optimize_within(
+                    points,
+                    F::from_f64(settings.break_at),
+                    settings.legs,
+                    settings.constraints,
+                    &settings.model.for_points(points),
+                    settings.budget,
+                )
+            })
+            .collect()
+    })
+}
+
+// A flight which can not be scored gets the exception instead of a result
+fn to_py_results<F: Float>(
+    py: Python,
+    results: Vec<Result<result::AnytimeResult<F>, error::ScoreError>>,
+    with_legs: bool,
+) -> PyResult<Vec<PyObject>> {
+    results
+        .into_iter()
+        .map(|result| match result {
+            Ok(result) => {
+                Ok(Py::new(py, PyOptimizationResult::new(result, with_legs))?.into_py(py))
+            }
+            Err(err) => Ok(PyErr::from(err).into_value(py).into_py(py)),
+        })
+        .collect()
+}
+
+// The result of optimize, see result::AnytimeResult. The status is "complete", "budget_exhausted"
+// or "cancelled". To keep the (path, distance) result of older versions, it can be unpacked
+// and indexed like this tuple, or like (path, distance, legs) with with_legs=True.
+#[pyclass(name = "Result")]
+struct PyOptimizationResult {
+    #[pyo3(get)]
+    path: Vec<usize>,
+    #[pyo3(get)]
+    distance: f64,
+    #[pyo3(get)]
+    legs: Vec<PyLeg>,
+    #[pyo3(get)]
+    status: &'static str,
+    #[pyo3(get)]
+    upper_bound: f64,
+    #[pyo3(get)]
+    dropped: usize,
+    #[pyo3(get)]
+    cache_hits: usize,
+    #[pyo3(get)]
+    cache_misses: usize,
+    #[pyo3(get)]
+    cache_scanned: usize,
+    with_legs: bool,
+}
+
+impl PyOptimizationResult {
+    fn new<F: Float>(anytime: result::AnytimeResult<F>, with_legs: bool) -> Self {
+        let result = anytime.result;
+        PyOptimizationResult {
+            path: result.path,
+            distance: result.distance.to_f64().unwrap(),
+            legs: result.legs.into_iter().map(PyLeg::from).collect(),
+            status: match anytime.status {
+                result::Status::Complete => "complete",
+                result::Status::BudgetExhausted => "budget_exhausted",
+                result::Status::Cancelled => "cancelled",
+            },
+            upper_bound: anytime.upper_bound.to_f64().unwrap(),
+            dropped: anytime.dropped,
+            cache_hits: anytime.cache.hits,
+            cache_misses: anytime.cache.misses,
+            cache_scanned: anytime.cache.scanned,
+            with_legs,
+        }
+    }
+
+    fn as_tuple<'py>(&self, py: Python<'py>) -> &'py PyTuple {
+        if self.with_legs {
+            PyTuple::new(
+                py,
+                [
+                    self.path.clone().into_py(py),
+                    self.distance.into_py(py),
+                    self.legs.clone().into_py(py),
+                ],
+            )
+        } else {
+            PyTuple::new(
+                py,
+                [self.path.clone().into_py(py), self.distance.into_py(py)],
+            )
+        }
+    }
+}
+
+#[pymethods]
+impl PyOptimizationResult {
+    fn __len__(&self) -> usize {
+        if self.with_legs {
+            3
+        } else {
+            2
+        }
+    }
+
+    fn __getitem__<'py>(&self, py: Python<'py>, index: &PyAny) -> PyResult<&'py PyAny> {
+        let tuple: &PyAny = self.as_tuple(py);
+        tuple.get_item(index)
+    }
+
+    fn __iter__(&self, py: Python) -> PyResult<PyObject> {
+        let tuple: &PyAny = self.as_tuple(py);
+        Ok(tuple.iter()?.into_py(py))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Result(path={:?}, distance={}, status='{}')",
+            self.path, self.distance, self.status
+        )
+    }
+}
+
+// The altitude rule: the maximum loss in meters, a percentage of the distance like "1%" or "none"
+fn parse_altitude_rule(rule: &PyAny) -> PyResult<point::AltitudeRule> {
+    let invalid = || {
+        PyValueError::new_err(
+            "altitude_rule must be a loss in meters, a percentage like '1%' or 'none'",
+        )
+    };
+    if let Ok(meters) = rule.extract::<i32>() {
+        return Ok(point::AltitudeRule::Fixed(meters));
+    }
+    let rule: &str = rule.extract().map_err(|_| invalid())?;
+    if rule == "none" {
+        Ok(point::AltitudeRule::Disabled)
+    } else {
+        let percent = rule.strip_suffix('%').ok_or_else(invalid)?;
+        let percent: f64 = percent.trim().parse().map_err(|_| invalid())?;
+        Ok(point::AltitudeRule::Relative(percent / 100.0))
+    }
+}
+
+// The engine rule by name, a running engine has a noise level above the threshold
+fn parse_engine_rule(engine: Option<&str>, threshold: u16) -> PyResult<point::EngineRule> {
+    match engine {
+        None => Ok(point::EngineRule::Ignore),
+        Some("no_turnpoints") => Ok(point::EngineRule::NoTurnpoints { threshold }),
+        Some("after_last_run") => Ok(point::EngineRule::AfterLastRun { threshold }),
+        _ => Err(PyValueError::new_err(
+            "engine must be one of 'no_turnpoints' or 'after_last_run'",
+        )),
+    }
+}
+
+// A value for each flight of optimize_many, or None for all of them
+fn per_flight<T: Clone>(
+    values: Option<Vec<T>>,
+    flights: usize,
+    name: &str,
+) -> PyResult<Vec<Option<T>>> {
+    match values {
+        None => Ok(vec![None; flights]),
+        Some(values) if values.len() == flights => Ok(values.into_iter().map(Some).collect()),
+        Some(_) => Err(PyValueError::new_err(format!(
+            "{} must contain a value for each flight",
+            name
+        ))),
+    }
+}
+
+// The budget of the search, the time is given in seconds and the memory of the distance matrix in bytes
+fn to_budget(time_budget: Option<f64>, memory_budget: Option<usize>) -> PyResult<free::Budget> {
+    let time = time_budget
+        .map(|seconds| {
+            Duration::try_from_secs_f64(seconds)
+                .map_err(|_| PyValueError::new_err("time_budget must be a positive number"))
+        })
+        .transpose()?;
+    Ok(free::Budget {
+        time,
+        memory: memory_budget,
+        ..Default::default()
+    })
+}
+
+#[pymodule]
+fn score_rs(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyLeg>()?;
+    m.add_class::<PyFlight>()?;
+    m.add_class::<PyProgress>()?;
+    m.add_class::<PyOptimizationResult>()?;
+
+    #[pyfn(m)]
+    #[pyo3(name = "read_igc", signature = (path, altitude="auto"))]
+    fn read_igc_py(py: Python, path: &str, altitude: &str) -> PyResult<PyFlight> {
+        let policy = match altitude {
+            "auto" => flight::AltitudePolicy::Auto,
+            "pressure" => flight::AltitudePolicy::Pressure,
+            "gnss" => flight::AltitudePolicy::Gnss,
+            _ => {
+                return Err(PyValueError::new_err(
+                    "altitude must be one of 'auto', 'pressure' or 'gnss'",
+                ))
+            }
+        };
+        let flight = flight::Flight::read(path, policy)?;
+        Ok(PyFlight::new(py, flight))
+    }
+
+    #[pyfn(m)]
+    #[pyo3(
+        name = "optimize",
+        signature = (longitude, latitude, alt, legs, time=None, start_time=None, end_time=None, max_duration=None, engine_noise=None, engine=None, engine_threshold=500, with_legs=false, exact=false, distance_model="vincenty", progress=None, altitude_rule=None, break_at=0.0, time_budget=None, memory_budget=None)
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn optimize_py<'py>(
+        py: Python<'py>,
+        longitude: PyReadonlyArray1<'py, f64>,
+        latitude: PyReadonlyArray1<'py, f64>,
+        alt: PyAltitudes<'py>,
+        legs: usize,
+        time: Option<PyReadonlyArray1<'py, i64>>,
+        start_time: Option<i64>,
+        end_time: Option<i64>,
+        max_duration: Option<i64>,
+        engine_noise: Option<PyReadonlyArray1<'py, f64>>,
+        engine: Option<&str>,
+        engine_threshold: u16,
+        with_legs: bool,
+        exact: bool,
+        distance_model: &str,
+        progress: Option<PyObject>,
+        altitude_rule: Option<&PyAny>,
+        break_at: f64,
+        time_budget: Option<f64>,
+        memory_budget: Option<usize>,
+    ) -> PyResult<PyOptimizationResult> {
+        let engine = parse_engine_rule(engine, engine_threshold)?;
+        let settings = Settings {
+            legs,
+            break_at,
+            constraints: point::Constraints {
+                altitude: altitude_rule
+                    .map(parse_altitude_rule)
+                    .transpose()?
+                    .unwrap_or_default(),
+                start_time,
+                end_time,
+                max_duration,
+                engine,
+            },
+            budget: to_budget(time_budget, memory_budget)?,
+            model: PyDistanceModel::parse(distance_model)?,
+        };
+        let points = to_points(&longitude, &latitude, &alt, time, engine_noise)?;
+        // exact results are calculated in double precision
+        if exact {
+            let result: result::AnytimeResult<f64> =
+                optimize_without_gil(py, &points, settings, progress.as_ref())?;
+            Ok(PyOptimizationResult::new(result, with_legs))
+        } else {
+            let result: result::AnytimeResult<f32> =
+                optimize_without_gil(py, &points, settings, progress.as_ref())?;
+            Ok(PyOptimizationResult::new(result, with_legs))
+        }
+    }
+
+    // Optimize a list of flights like optimize. start_time, end_time and engine_noise are lists
+    // with a value (or None) for each flight, all other arguments apply to all flights.
+    // Invalid arguments raise an exception. A flight which can not be scored does not raise,
+    // its exception (e.g. a ValueError) is returned in the list instead of its Result.
+    #[pyfn(m)]
+    #[pyo3(
+        name = "optimize_many",
+        signature = (flights, legs, start_time=None, end_time=None, max_duration=None, engine_noise=None, engine=None, engine_threshold=500, with_legs=false, exact=false, distance_model="vincenty", altitude_rule=None, break_at=0.0, time_budget=None, memory_budget=None)
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn optimize_many_py<'py>(
+        py: Python<'py>,
+        flights: Vec<PyTrack<'py>>,
+        legs: usize,
+        start_time: Option<Vec<Option<i64>>>,
+        end_time: Option<Vec<Option<i64>>>,
+        max_duration: Option<i64>,
+        engine_noise: Option<Vec<Option<PyReadonlyArray1<'py, f64>>>>,
+        engine: Option<&str>,
+        engine_threshold: u16,
+        with_legs: bool,
+        exact: bool,
+        distance_model: &str,
+        altitude_rule: Option<&PyAny>,
+        break_at: f64,
+        time_budget: Option<f64>,
+        memory_budget: Option<usize>,
+    ) -> PyResult<Vec<PyObject>> {
+        let settings = Settings {
+            legs,
+            break_at,
+            constraints: point::Constraints {
+                altitude: altitude_rule
+                    .map(parse_altitude_rule)
+                    .transpose()?
+                    .unwrap_or_default(),
+                max_duration,
+                engine: parse_engine_rule(engine, engine_threshold)?,
+                ..Default::default()
+            },
+            budget: to_budget(time_budget, memory_budget)?,
+            model: PyDistanceModel::parse(distance_model)?,
+        };
+        let start_time = per_flight(start_time, flights.len(), "start_time")?;
+        let end_time = per_flight(end_time, flights.len(), "end_time")?;
+        let engine_noise = per_flight(engine_noise, flights.len(), "engine_noise")?;
+        let flights = flights
+            .iter()
+            .zip(engine_noise)
+            .zip(start_time.into_iter().zip(end_time))
+            .map(|((track, engine_noise), (start_time, end_time))| {
+                let points = track.to_points(engine_noise.flatten())?;
+                let constraints = point::Constraints {
+                    start_time: start_time.flatten(),
+                    end_time: end_time.flatten(),
+                    ..settings.constraints
+                };
+                Ok((
+                    points,
+                    Settings {
+                        constraints,
+                        ..settings
+                    },
+                ))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        if exact {
+            let results = optimize_all::<f64>(py, &flights);
+            to_py_results(py, results, with_legs)
+        } else {
+            let results = optimize_all::<f32>(py, &flights);
+            to_py_results(py, results, with_legs)
+        }
+    }
+
+    #[pyfn(m)]
+    #[pyo3(name = "to_geojson", signature = (longitude, latitude, alt, path, distance_model="vincenty"))]
+    fn to_geojson_py<'py>(
+        longitude: PyReadonlyArray1<'py, f64>,
+        latitude: PyReadonlyArray1<'py, f64>,
+        alt: PyAltitudes<'py>,
+        path: Vec<usize>,
+        distance_model: &str,
+    ) -> PyResult<String> {
+        let points = to_points(&longitude, &latitude, &alt, None, None)?;
+        let result = to_result(path, &points, PyDistanceModel::parse(distance_model)?)?;
+        Ok(export::to_geojson(&points, &result))
+    }
+
+    #[pyfn(m)]
+    #[pyo3(name = "to_kml", signature = (longitude, latitude, alt, path, distance_model="vincenty"))]
+    fn to_kml_py<'py>(
+        longitude: PyReadonlyArray1<'py, f64>,
+        latitude: PyReadonlyArray1<'py, f64>,
+        alt: PyAltitudes<'py>,
+        path: Vec<usize>,
+        distance_model: &str,
+    ) -> PyResult<String> {
+        let points = to_points(&longitude, &latitude, &alt, None, None)?;
+        let result = to_result(path, &points, PyDistanceModel::parse(distance_model)?)?;
+        Ok(export::to_kml(&points, &result))
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use flat_projection::FlatPoint;
+
+use crate::flat::to_flat_points;
+use crate::point::{AltitudeRule, Constraints, Path, Point};
+
+// The largest distance in km of a removed fix from the segment between its neighbours. It absorbs
+// the rounding of the projection, a path through a removed fix is at most twice as much longer.
+const TOLERANCE: f64 = 1e-9;
+
+// The fixes of a route which remain after the track reduction, in their original order
+#[derive(Debug, PartialEq)]
+pub struct Reduction {
+    pub kept: Vec<usize>,
+    pub dropped: usize,
+}
+
+impl Reduction {
+    // Map a path on the kept fixes back to the indices of the whole route
+    pub fn map_path(&self, path: &[usize]) -> Path {
+        path.iter().map(|&index| self.kept[index]).collect()
+    }
+}
+
+// Remove the fixes which can not change the optimal distance of a free distance optimization.
+// The fixes are removed one at a time, each removal keeps the optimum of the remaining fixes:
+//
+// - A fix which is interchangeable with the previous remaining fix: every path through it can use
+//   the previous fix instead, without changing its distance or validity.
+// - A fix which lies on the segment between the previous and the next remaining fix (in the flat
+//   projection, which is affine): the distance of the legs to and from a turnpoint is a convex
+//   function of its position, so on the segment it is largest at one of its ends. A path through
+//   the fix can use this neighbour instead, which keeps the order of the path (the neighbour may
+//   repeat the previous or next turnpoint) and does not make it shorter. This holds for the first
+//   and the last turnpoint as well. The path stays valid if the constraints can not tell the
+//   three fixes apart and allow turnpoints on them.
+//
+// Fixes on which the engine rule forbids a turnpoint are kept, the distance matrix excludes them.
+pub fn reduce<T: Point>(route: &[T], constraints: Constraints) -> Reduction {
+    let flat_points: Vec<FlatPoint<f64>> = to_flat_points(route);
+    let mut kept: Vec<usize> = Vec::with_capacity(route.len());
+    for index in 0..route.len() {
+        if let Some(&previous) = kept.last() {
+            if interchangeable(route, &flat_points, constraints, previous, index) {
+                kept.pop();
+            }
+        }
+        kept.push(index);
+        while let [.., before, between, after] = kept[..] {
+            if !dominated(route, &flat_points, constraints, [before, between, after]) {
+                break;
+            }
+            kept.remove(kept.len() - 2);
+        }
+    }
+    kept.shrink_to_fit();
+    Reduction {
+        dropped: route.len() - kept.len(),
+        kept,
+    }
+}
+
+// Return if two fixes have the same position and the constraints can not tell them apart
+fn interchangeable<T: Point>(
+    route: &[T],
+    flat_points: &[FlatPoint<f64>],
+    constraints: Constraints,
+    first: usize,
+    second: usize,
+) -> bool {
+    flat_points[first] == flat_points[second]
+        && indistinguishable(route, constraints, first, second)
+}
+
+// Return if the middle of three fixes lies on the segment between the others and the
+// constraints allow turnpoints on all of them, but can not tell them apart
+fn dominated<T: Point>(
+    route: &[T],
+    flat_points: &[FlatPoint<f64>],
+    constraints: Constraints,
+    [before, between, after]: [usize; 3],
+) -> bool {
+    [before, between, after]
+        .iter()
+        .all(|&index| constraints.engine.allows_turnpoint(&route[index]))
+        && indistinguishable(route, constraints, before, between)
+        && indistinguishable(route, constraints, between, after)
+        && distance_to_segment(
+            &flat_points[between],
+            &flat_points[before],
+            &flat_points[after],
+        ) <= TOLERANCE
+}
+
+// Return if the constraints treat two fixes the same when they are the start or the finish
+fn indistinguishable<T: Point>(
+    route: &[T],
+    constraints: Constraints,
+    first: usize,
+    second: usize,
+) -> bool {
+    (constraints.altitude == AltitudeRule::Disabled
+        || route[first].altitude() == route[second].altitude())
+        && (constraints.max_duration.is_none()
+            || route[first].timestamp() == route[second].timestamp())
+        && constraints.engine.allows_turnpoint(&route[first])
+            == constraints.engine.allows_turnpoint(&route[second])
+}
+
+fn distance_to_segment(
+    point: &FlatPoint<f64>,
+    start: &FlatPoint<f64>,
+    stop: &FlatPoint<f64>,
+) -> f64 {
+    let (dx, dy) = (stop.x - start.x, stop.y - start.y);
+    let length = dx * dx + dy * dy;
+    let ratio = if length > 0.0 {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = FlatPoint {
+        x: start.x + ratio * dx,
+        y: start.y + ratio * dy,
+    };
+    point.distance(&closest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flight::{AltitudePolicy, Flight};
+    use crate::point::{EngineRule, PointImpl};
+
+    fn point(latitude: f64, altitude: i16, engine_noise: u16) -> PointImpl {
+        PointImpl {
+            latitude,
+            longitude: 10.0,
+            altitude,
+            timestamp: None,
+            engine_noise: Some(engine_noise),
+        }
+    }
+
+    #[test]
+    fn reduce_drops_interchangeable_and_dominated_fixes() {
+        let route = vec![
+            point(50.0, 100, 0),
+            point(50.0, 100, 0),
+            point(50.1, 100, 0),
+            point(50.1, 200, 0),
+            point(50.2, 200, 900),
+            point(50.3, 200, 0),
+            point(50.3, 200, 0),
+            point(50.4, 200, 0),
+            point(50.5, 200, 0),
+        ];
+        let constraints = Constraints {
+            engine: EngineRule::NoTurnpoints { threshold: 500 },
+            ..Default::default()
+        };
+        // the engine run is kept, so the fixes next to it are not dominated
+        let reduction = reduce(&route, constraints);
+        assert_eq!(reduction.kept, [1, 2, 3, 4, 6, 8]);
+        assert_eq!(reduction.dropped, 3);
+        assert_eq!(reduction.map_path(&[0, 2, 5]), [1, 3, 8]);
+
+        // without an altitude rule, the altitude of the fixes does not matter
+        let reduction = reduce(&route, AltitudeRule::Disabled.into());
+        assert_eq!(reduction.kept, [1, 8]);
+    }
+
+    #[test]
+    fn reduce_drops_fixes_between_their_neighbours() {
+        let mut between = point(50.05, 100, 0);
+        between.longitude = 10.05;
+        let mut beside = between.clone();
+        beside.longitude = 10.051;
+        let mut end = point(50.1, 100, 0);
+        end.longitude = 10.1;
+        let start = point(50.0, 100, 0);
+        let constraints = AltitudeRule::Disabled.into();
+
+        let reduction = reduce(&[start.clone(), between, end.clone()], constraints);
+        assert_eq!(reduction.kept, [0, 2]);
+        let reduction = reduce(&[start, beside, end], constraints);
+        assert_eq!(reduction.kept, [0, 1, 2]);
+    }
+
+    #[test]
+    fn reduce_shrinks_real_tracks() {
+        // on the grid of the IGC coordinates, many fixes of straight glides lie on a line
+        let flight = Flight::read("fixtures/schunk_1000m.igc", AltitudePolicy::Auto).unwrap();
+        assert_eq!(flight.fixes.len(), 37118);
+        let reduction = reduce(&flight.fixes, AltitudeRule::Disabled.into());
+        assert_eq!(reduction.dropped, 6662);
+        // with the altitude rule, only fixes at the same altitude are dominated
+        let reduction = reduce(&flight.fixes, Constraints::default());
+        assert_eq!(reduction.dropped, 375);
+    }
+}
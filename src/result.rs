@@ -1,18 +1,138 @@
+use crate::cache::CacheStats;
+use crate::distance::DistanceModel;
+use crate::error::ScoreError;
+use crate::float::Float;
 use crate::graph::StartCandidate;
-use crate::point::{ApproxDistance, Path, Point, Valid, VincentyDistance};
+use crate::point::{ApproxDistance, Constraints, ModelDistance, Path, Point, Valid};
 use flat_projection::FlatPoint;
 use ord_subset::OrdVar;
 
 #[derive(Debug)]
-pub struct OptimizationResult {
+pub struct OptimizationResult<F = f32> {
     pub path: Path,
-    pub distance: f32,
+    pub distance: F,
+    pub legs: Vec<Leg<F>>,
 }
 
-impl OptimizationResult {
-    pub fn new<T: Point>(path: Path, route: &[T]) -> Self {
-        let distance = route.cum_distance(&path);
-        OptimizationResult { path, distance }
+impl<F: Float> OptimizationResult<F> {
+    // Measure the legs of the path with the distance model
+    pub fn new<T: Point, M: DistanceModel>(
+        path: Path,
+        route: &[T],
+        model: &M,
+    ) -> Result<Self, ScoreError> {
+        let legs = Leg::from_path(&path, route, model)?;
+        let distance = legs.iter().map(|leg| leg.distance).sum();
+        Ok(OptimizationResult {
+            path,
+            distance,
+            legs,
+        })
+    }
+}
+
+// A single leg of a result between two turnpoints, with its distance in km,
+// the initial bearing in degrees and the altitudes at both ends
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leg<F = f32> {
+    pub start: usize,
+    pub stop: usize,
+    pub distance: F,
+    pub bearing: F,
+    pub start_altitude: i16,
+    pub stop_altitude: i16,
+}
+
+impl<F: Float> Leg<F> {
+    pub fn new<T: Point, M: DistanceModel>(
+        start: usize,
+        stop: usize,
+        route: &[T],
+        model: &M,
+    ) -> Result<Self, ScoreError> {
+        let (distance, bearing) = model
+            .inverse(&route[start], &route[stop])
+            .ok_or(ScoreError::NoConvergence { start, stop })?;
+        Ok(Leg {
+            start,
+            stop,
+            distance,
+            bearing,
+            start_altitude: route[start].altitude(),
+            stop_altitude: route[stop].altitude(),
+        })
+    }
+
+    // Return the legs between all consecutive turnpoints of the path
+    pub fn from_path<T: Point, M: DistanceModel>(
+        path: &[usize],
+        route: &[T],
+        model: &M,
+    ) -> Result<Vec<Self>, ScoreError> {
+        path.iter()
+            .zip(path.iter().skip(1))
+            .map(|(&start, &stop)| Leg::new(start, stop, route, model))
+            .collect()
+    }
+}
+
+// The result of an optimization within a budget, see free::optimize_within. If the search
+// was stopped before it completed, the result is the best valid path found so far.
+#[derive(Debug)]
+pub struct AnytimeResult<F = f32> {
+    pub result: OptimizationResult<F>,
+    // An upper bound of the distance of any valid path, calculated in the flat projection.
+    // If the search completed, this is the flat distance of the result.
+    pub upper_bound: F,
+    pub status: Status,
+    // The number of fixes which the track reduction removed before the search, see reduce::reduce
+    pub dropped: usize,
+    // How often the cache of the search ruled out a start candidate, see cache::Cache
+    pub cache: CacheStats,
+}
+
+// How the search of an optimization ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    // The result is optimal, unless no path is longer than break_at
+    Complete,
+    // The budget ran out
+    BudgetExhausted,
+    // The progress callback cancelled the search
+    Cancelled,
+}
+
+// The result of a closed course: the turnpoints are stored in path, start and stop are
+// the points that close the course. The distance is measured along the turnpoints only,
+// the last leg returns to the first turnpoint.
+#[derive(Debug)]
+pub struct ClosedResult<F = f32> {
+    pub path: Path,
+    pub start: usize,
+    pub stop: usize,
+    pub distance: F,
+    pub legs: Vec<Leg<F>>,
+}
+
+impl<F: Float> ClosedResult<F> {
+    pub fn new<T: Point, M: DistanceModel>(
+        path: Path,
+        start: usize,
+        stop: usize,
+        route: &[T],
+        model: &M,
+    ) -> Result<Self, ScoreError> {
+        let mut closed_path = path.clone();
+        closed_path.push(path[0]);
+        let legs = Leg::from_path(&closed_path, route, model)?;
+        let distance = legs.iter().map(|leg| leg.distance).sum();
+        Ok(ClosedResult {
+            path,
+            start,
+            stop,
+            distance,
+            legs,
+        })
     }
 }
 
@@ -22,8 +142,8 @@ pub struct Bound {
     pub stop: usize,
 }
 
-impl From<&[StartCandidate]> for Bound {
-    fn from(candidates: &[StartCandidate]) -> Self {
+impl<F> From<&[StartCandidate<F>]> for Bound {
+    fn from(candidates: &[StartCandidate<F>]) -> Self {
         Bound {
             start: candidates.iter().map(|c| c.start).min().unwrap(),
             stop: candidates.iter().map(|c| c.start).max().unwrap(),
@@ -32,21 +152,24 @@ impl From<&[StartCandidate]> for Bound {
 }
 
 #[derive(Debug)]
-struct SlidingResult {
+struct SlidingResult<F> {
     start: usize,
     stop: usize,
-    distance: f32,
+    distance: F,
 }
 
-impl OptimizationResult {
+impl<F: Float> OptimizationResult<F> {
     // Hold inner parts of path constant and adjust (wiggle) first and last to optimum
-    // The result is not optimal, but comes in many cases very close, so it is a good starting point
-    pub fn optimize_by_sliding<T: Point>(
+    // The result is not optimal, but comes in many cases very close, so it is a good starting point.
+    // The distance of the new path is measured with the model.
+    pub fn optimize_by_sliding<T: Point, M: DistanceModel>(
         &self,
         route: &[T],
-        flat_points: &[FlatPoint<f32>],
+        flat_points: &[FlatPoint<F>],
         start_window: &Bound,
-    ) -> Option<OptimizationResult> {
+        constraints: Constraints,
+        model: &M,
+    ) -> Option<Self> {
         if self.path.len() < 3 {
             return None;
         }
@@ -57,14 +180,16 @@ impl OptimizationResult {
         let penultimate = self.path[self.path.len() - 2];
         let last_leg_start = penultimate..route.len();
 
+        // all the other legs stay constant, so we ignore them to find the max.
+        // They are only needed to check the altitude rule
+        let inner_legs = flat_points.cum_distance(&self.path[1..self.path.len() - 1].to_vec());
+
         let sliding_result = first_leg_start
             .filter_map(|start| {
+                let first_leg = flat_points.distance(start, second);
                 last_leg_start
                     .clone()
-                    .filter(|stop| route.valid(start, *stop))
                     .map(|stop| {
-                        // all the other legs stay constant, so we ignore them to find the max
-                        let first_leg = flat_points.distance(start, second);
                         let last_leg = flat_points.distance(stop, penultimate);
                         SlidingResult {
                             start,
@@ -72,29 +197,106 @@ impl OptimizationResult {
                             distance: first_leg + last_leg,
                         }
                     })
+                    .filter(|slide| {
+                        route.valid(start, slide.stop, constraints, inner_legs + slide.distance)
+                    })
                     .max_by_key(|slide| OrdVar::new_checked(slide.distance))
             })
             .max_by_key(|slide| OrdVar::new_checked(slide.distance));
 
-        sliding_result.map(|slide| self.from_slide_result(route, slide))
+        sliding_result.and_then(|slide| self.with_slide_result(route, slide, model))
     }
 
-    // create a new OptimizationResult after the sliding optimization
-    fn from_slide_result<T: Point>(&self, route: &[T], slide: SlidingResult) -> Self {
+    // create a new OptimizationResult after the sliding optimization,
+    // None if the distance of the new path does not converge
+    fn with_slide_result<T: Point, M: DistanceModel>(
+        &self,
+        route: &[T],
+        slide: SlidingResult<F>,
+        model: &M,
+    ) -> Option<Self> {
         let mut path = self.path.clone();
         path[0] = slide.start;
         path[self.path.len() - 1] = slide.stop;
-        let distance = route.cum_distance(&path);
-        OptimizationResult { path, distance }
+        let distance = route.cum_distance(&path, model)?;
+        Some(OptimizationResult {
+            path,
+            distance,
+            legs: Vec::new(),
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::distance::{Karney, Vincenty};
     use crate::point::PointImpl;
     use assert_approx_eq::assert_approx_eq;
 
+    #[test]
+    fn new_calculates_legs() {
+        let route = vec![
+            PointImpl {
+                latitude: 50.0,
+                longitude: 10.0,
+                altitude: 500,
+                timestamp: None,
+                engine_noise: None,
+            },
+            PointImpl {
+                latitude: 51.0,
+                longitude: 10.0,
+                altitude: 1500,
+                timestamp: None,
+                engine_noise: None,
+            },
+            PointImpl {
+                latitude: 51.0,
+                longitude: 11.0,
+                altitude: 1000,
+                timestamp: None,
+                engine_noise: None,
+            },
+        ];
+        let result: OptimizationResult =
+            OptimizationResult::new(vec![0, 1, 2], &route, &Vincenty).unwrap();
+        assert_eq!(result.legs.len(), 2);
+        assert_eq!((result.legs[0].start, result.legs[0].stop), (0, 1));
+        assert_eq!(result.legs[0].start_altitude, 500);
+        assert_eq!(result.legs[0].stop_altitude, 1500);
+        assert_approx_eq!(result.legs[0].distance, 111.23, 0.01);
+        assert_approx_eq!(result.legs[0].bearing, 0.0, 0.01);
+        assert_approx_eq!(result.legs[1].bearing, 89.61, 0.01);
+        assert_approx_eq!(
+            result.distance,
+            result.legs[0].distance + result.legs[1].distance
+        );
+    }
+
+    #[test]
+    fn new_reports_non_convergence() {
+        let route: Vec<PointImpl> = [(0.0, 0.0), (0.5, 179.5)]
+            .iter()
+            .map(|&(latitude, longitude)| PointImpl {
+                latitude,
+                longitude,
+                altitude: 0,
+                timestamp: None,
+                engine_noise: None,
+            })
+            .collect();
+        let result: Result<OptimizationResult, _> =
+            OptimizationResult::new(vec![0, 1], &route, &Vincenty);
+        assert_eq!(
+            result.unwrap_err(),
+            ScoreError::NoConvergence { start: 0, stop: 1 }
+        );
+        let result: OptimizationResult =
+            OptimizationResult::new(vec![0, 1], &route, &Karney).unwrap();
+        assert!(result.distance > 19900.0);
+    }
+
     #[test]
     fn bound_finds_min_and_max() {
         let candidates = vec![
@@ -113,56 +315,66 @@ mod tests {
     }
 
     #[test]
-    fn from_slide_result_updates_path() {
+    fn with_slide_result_updates_path() {
         let route = vec![
             PointImpl {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
             PointImpl {
                 latitude: 1.0,
                 longitude: 1.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
         ];
-        let result = OptimizationResult {
+        let result: OptimizationResult = OptimizationResult {
             path: vec![1, 0, 0, 1],
             distance: 0.0,
+            legs: Vec::new(),
         };
         let slide = SlidingResult {
             start: 0,
             stop: 0,
             distance: 100.0,
         };
-        let improved = result.from_slide_result(&route, slide);
+        let improved = result.with_slide_result(&route, slide, &Vincenty).unwrap();
         assert_eq!(improved.path, vec![0, 0, 0, 0]);
     }
 
     #[test]
-    fn from_slide_result_updates_distance() {
+    fn with_slide_result_updates_distance() {
         let route = vec![
             PointImpl {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
             PointImpl {
                 latitude: 10.0,
                 longitude: 10.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             },
         ];
-        let result = OptimizationResult {
+        let result: OptimizationResult = OptimizationResult {
             path: vec![0, 1, 0, 0],
             distance: 100.0,
+            legs: Vec::new(),
         };
         let slide = SlidingResult {
             start: 0,
             stop: 0,
             distance: 100.0,
         };
-        let improved = result.from_slide_result(&route, slide);
+        let improved = result.with_slide_result(&route, slide, &Vincenty).unwrap();
         assert_approx_eq!(improved.distance, 3130.22, 0.01);
     }
 
@@ -174,12 +386,15 @@ mod tests {
                 latitude: 0.0,
                 longitude: 0.0,
                 altitude: 0,
+                timestamp: None,
+                engine_noise: None,
             };
             5
         ];
-        let result = OptimizationResult {
+        let result: OptimizationResult = OptimizationResult {
             path: vec![1, 1, 1],
             distance: 100.0,
+            legs: Vec::new(),
         };
         let flat_points = vec![
             FlatPoint { x: 0.0, y: 0.0 },
@@ -189,7 +404,13 @@ mod tests {
             FlatPoint { x: 4.0, y: 4.0 },
         ];
         let start_window = Bound { start: 0, stop: 5 };
-        let improved = result.optimize_by_sliding(&route, &flat_points, &start_window);
+        let improved = result.optimize_by_sliding(
+            &route,
+            &flat_points,
+            &start_window,
+            Constraints::default(),
+            &Vincenty,
+        );
         assert_eq!(improved.unwrap().path, vec![0, 1, 4]);
     }
 }
@@ -0,0 +1,540 @@
+// Find the best closed triangle in a track.
+//
+// A triangle consists of three turnpoints a < b < c (by index). For FAI triangles, every leg
+// of the triangle (a -> b, b -> c, c -> a) has to be at least 28% of the total distance, flat
+// triangles have no restriction on the legs. In both cases the track has to be closed: there
+// has to be a start point before a and a finish point after c which are not further apart
+// than the closing distance and comply with the altitude rule.
+//
+// Checking all combinations of three turnpoints is not feasible for tracks with tens of
+// thousands of points, and unlike the free distance the search does not use half_dist_matrix:
+// its memory grows with the square of the points (about 2.8 GB for 37k fixes) and a triangle
+// needs the closing leg c -> a, which a graph over the forward distances can not bound.
+// Instead, the track is split into a binary tree of index ranges with their bounding boxes.
+// A triple of ranges has an upper bound on the achievable distance, which is calculated from
+// the maximum distances between their bounding boxes. The triples are refined in best-first
+// order, so the first triple that consists of single points only is the optimal triangle.
+
+use std::collections::BinaryHeap;
+
+use flat_projection::FlatPoint;
+use ord_subset::OrdVar;
+
+use crate::closing::{find_best_closed, Closing};
+use crate::distance::DistanceModel;
+use crate::error::{check_route, ScoreError};
+use crate::flat::to_flat_points;
+use crate::float::Float;
+use crate::parallel::*;
+use crate::point::{AltitudeRule, Point};
+use crate::result::{ClosedResult, Leg};
+
+// Minimum share of every leg on the total distance of an FAI triangle
+const FAI_MIN_LEG: f64 = 0.28;
+
+// Number of points the track is reduced to when searching for an initial solution
+const SAMPLE_SIZE: usize = 150;
+
+// Find the three turnpoints which form the FAI triangle with the largest distance.
+// The start and finish of the triangle must not be further apart than closing_distance km
+// and have to comply with the altitude rule. The distances are calculated with the float type
+// of closing_distance, the distance model measures the legs of the result and checks the
+// minimum leg length.
+pub fn optimize<F: Float, T: Point, M: DistanceModel>(
+    route: &[T],
+    closing_distance: F,
+    altitude: AltitudeRule,
+    model: &M,
+) -> Result<ClosedResult<F>, ScoreError> {
+    optimize_with_min_leg(
+        route,
+        closing_distance,
+        altitude,
+        F::from_f64(FAI_MIN_LEG),
+        model,
+    )
+}
+
+// Find the three turnpoints which form the triangle with the largest distance, regardless of
+// the leg lengths. The start and finish of the triangle must not be further apart than
+// closing_distance km and have to comply with the altitude rule. The distances are calculated
+// with the float type of closing_distance, the distance model measures the legs of the result.
+pub fn optimize_flat<F: Float, T: Point, M: DistanceModel>(
+    route: &[T],
+    closing_distance: F,
+    altitude: AltitudeRule,
+    model: &M,
+) -> Result<ClosedResult<F>, ScoreError> {
+    optimize_with_min_leg(route, closing_distance, altitude, F::zero(), model)
+}
+
+fn optimize_with_min_leg<F: Float, T: Point, M: DistanceModel>(
+    route: &[T],
+    closing_distance: F,
+    altitude: AltitudeRule,
+    min_leg: F,
+    model: &M,
+) -> Result<ClosedResult<F>, ScoreError> {
+    check_route(route, 3)?;
+    let flat_points = to_flat_points(route);
+
+    let (triangle, closing) =
+        find_best_closed(&flat_points, route, closing_distance, altitude, |closing| {
+            find_best_triangle(&flat_points, closing, min_leg, |path| {
+                satisfies_min_leg(path, route, min_leg, model)
+            })
+            .map(|triangle| (triangle.path, triangle.distance))
+        })
+        .ok_or(ScoreError::NoValidSolution)?;
+    let (start, stop) = closing.reach[triangle[0]];
+    ClosedResult::new(triangle, start, stop, route, model)
+}
+
+#[derive(Debug)]
+pub struct Triangle<F> {
+    pub path: Vec<usize>,
+    pub distance: F,
+}
+
+// Check the minimum leg length with the distances of the model that are reported in the result.
+// The search uses the flat projection, which can accept a triangle just at the limit.
+// A triangle whose distances do not converge can not be reported and is rejected.
+fn satisfies_min_leg<F: Float, T: Point, M: DistanceModel>(
+    path: &[usize],
+    route: &[T],
+    min_leg: F,
+    model: &M,
+) -> bool {
+    let Ok(legs) = Leg::from_path(&[path[0], path[1], path[2], path[0]], route, model) else {
+        return false;
+    };
+    let distance: F = legs.iter().map(|leg| leg.distance).sum();
+    legs.iter().all(|leg| leg.distance >= min_leg * distance)
+}
+
+// Branch and bound search for the best triangle with every leg being at least
+// min_leg times the total distance. Triangles which are rejected by accept are skipped.
+pub fn find_best_triangle<F, A>(
+    flat_points: &[FlatPoint<F>],
+    closing: &Closing,
+    min_leg: F,
+    accept: A,
+) -> Option<Triangle<F>>
+where
+    F: Float,
+    A: Fn(&[usize]) -> bool + Sync,
+{
+    if flat_points.len() < 3 {
+        return None;
+    }
+    let tree = BoxTree::new(flat_points);
+    let best = find_sampled_triangle(flat_points, closing, min_leg, &accept);
+    let best_distance = best
+        .as_ref()
+        .map_or(F::zero(), |triangle| triangle.distance);
+
+    let mut queue = BinaryHeap::new();
+    let root = [0, 0, 0];
+    if let Some(bound) = tree.upper_bound(&root, flat_points, closing, min_leg) {
+        queue.push((OrdVar::new(bound), root));
+    }
+
+    while let Some((bound, nodes)) = queue.pop() {
+        if *bound <= best_distance {
+            break;
+        }
+        // split the node with most points
+        let (slot, node) = nodes
+            .iter()
+            .enumerate()
+            .max_by_key(|(slot, node)| (tree.nodes[**node].len(), usize::MAX - *slot))
+            .map(|(slot, node)| (slot, *node))
+            .unwrap();
+
+        let children = match tree.nodes[node].children {
+            Some(children) => children,
+            None => {
+                // all nodes are single points, the bound is the exact distance of a valid triangle
+                let path: Vec<usize> = nodes.iter().map(|node| tree.nodes[*node].start).collect();
+                if !accept(&path) {
+                    continue;
+                }
+                return Some(Triangle {
+                    path,
+                    distance: *bound,
+                });
+            }
+        };
+
+        for child in [children.0, children.1] {
+            let mut next = nodes;
+            next[slot] = child;
+            if let Some(bound) = tree.upper_bound(&next, flat_points, closing, min_leg) {
+                if bound > best_distance {
+                    queue.push((OrdVar::new(bound), next));
+                }
+            }
+        }
+    }
+    best
+}
+
+// Check all triangles on an evenly sampled subset of the points to get a lower bound fast
+fn find_sampled_triangle<F, A>(
+    flat_points: &[FlatPoint<F>],
+    closing: &Closing,
+    min_leg: F,
+    accept: &A,
+) -> Option<Triangle<F>>
+where
+    F: Float,
+    A: Fn(&[usize]) -> bool + Sync,
+{
+    let step = (flat_points.len() / SAMPLE_SIZE).max(1);
+    let samples: Vec<usize> = (0..flat_points.len()).step_by(step).collect();
+
+    opt_par_iter(&samples)
+        .enumerate()
+        .filter_map(|(i, &a)| {
+            let mut best: Option<Triangle<F>> = None;
+            for (j, &b) in samples.iter().enumerate().skip(i + 1) {
+                for &c in samples.iter().skip(j + 1) {
+                    if !closing.closes(a, c) {
+                        break;
+                    }
+                    let distance = triangle_distance(flat_points, a, b, c, min_leg);
+                    if distance
+                        > best
+                            .as_ref()
+                            .map_or(F::zero(), |triangle| triangle.distance)
+                        && accept(&[a, b, c])
+                    {
+                        best = Some(Triangle {
+                            path: vec![a, b, c],
+                            distance,
+                        });
+                    }
+                }
+            }
+            best
+        })
+        .max_by_key(|triangle| OrdVar::new_checked(triangle.distance))
+}
+
+// Return the distance of the triangle or zero if it does not satisfy the minimum leg length
+fn triangle_distance<F: Float>(
+    flat_points: &[FlatPoint<F>],
+    a: usize,
+    b: usize,
+    c: usize,
+    min_leg: F,
+) -> F {
+    let legs = [
+        flat_points[a].distance(&flat_points[b]),
+        flat_points[b].distance(&flat_points[c]),
+        flat_points[c].distance(&flat_points[a]),
+    ];
+    let distance: F = legs.iter().copied().sum();
+    if legs.iter().all(|&leg| leg >= min_leg * distance) {
+        distance
+    } else {
+        F::zero()
+    }
+}
+
+struct BoxNode<F: Float> {
+    start: usize,
+    stop: usize,
+    min: FlatPoint<F>,
+    max: FlatPoint<F>,
+    children: Option<(usize, usize)>,
+}
+
+impl<F: Float> BoxNode<F> {
+    fn len(&self) -> usize {
+        self.stop - self.start
+    }
+
+    // minimum distance between any point in self and any point in other
+    fn min_distance(&self, other: &Self) -> F {
+        let dx = (other.min.x - self.max.x)
+            .max(self.min.x - other.max.x)
+            .max(F::zero());
+        let dy = (other.min.y - self.max.y)
+            .max(self.min.y - other.max.y)
+            .max(F::zero());
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    // maximum distance between any point in self and any point in other
+    fn max_distance(&self, other: &Self) -> F {
+        let dx = (self.max.x - other.min.x).max(other.max.x - self.min.x);
+        let dy = (self.max.y - other.min.y).max(other.max.y - self.min.y);
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+// Binary tree of index ranges [start, stop) with their bounding boxes, the root is at index 0
+struct BoxTree<F: Float> {
+    nodes: Vec<BoxNode<F>>,
+}
+
+impl<F: Float> BoxTree<F> {
+    fn new(flat_points: &[FlatPoint<F>]) -> Self {
+        let mut tree = BoxTree {
+            nodes: Vec::with_capacity(2 * flat_points.len()),
+        };
+        tree.build(flat_points, 0, flat_points.len());
+        tree
+    }
+
+    fn build(&mut self, flat_points: &[FlatPoint<F>], start: usize, stop: usize) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(BoxNode {
+            start,
+            stop,
+            min: flat_points[start],
+            max: flat_points[start],
+            children: None,
+        });
+        if stop - start == 1 {
+            return index;
+        }
+        let middle = (start + stop) / 2;
+        let left = self.build(flat_points, start, middle);
+        let right = self.build(flat_points, middle, stop);
+        let (left_node, right_node) = (&self.nodes[left], &self.nodes[right]);
+        let min = FlatPoint {
+            x: left_node.min.x.min(right_node.min.x),
+            y: left_node.min.y.min(right_node.min.y),
+        };
+        let max = FlatPoint {
+            x: left_node.max.x.max(right_node.max.x),
+            y: left_node.max.y.max(right_node.max.y),
+        };
+        let node = &mut self.nodes[index];
+        node.min = min;
+        node.max = max;
+        node.children = Some((left, right));
+        index
+    }
+
+    // Upper bound on the distance of any valid triangle with the turnpoints in the given nodes.
+    // Return None if there is no valid triangle at all
+    fn upper_bound(
+        &self,
+        nodes: &[usize; 3],
+        flat_points: &[FlatPoint<F>],
+        closing: &Closing,
+        min_leg: F,
+    ) -> Option<F> {
+        let (first, second, third) = (
+            &self.nodes[nodes[0]],
+            &self.nodes[nodes[1]],
+            &self.nodes[nodes[2]],
+        );
+        // there have to be a < b < c with a, b and c in the respective nodes
+        let b = second.start.max(first.start + 1);
+        if b >= second.stop || b + 1 >= third.stop {
+            return None;
+        }
+        if !closing.closes(first.stop - 1, third.start) {
+            return None;
+        }
+
+        if nodes.iter().all(|node| self.nodes[*node].len() == 1) {
+            let distance =
+                triangle_distance(flat_points, first.start, second.start, third.start, min_leg);
+            return if distance > F::zero() {
+                Some(distance)
+            } else {
+                None
+            };
+        }
+
+        let legs = [
+            first.max_distance(second),
+            second.max_distance(third),
+            third.max_distance(first),
+        ];
+        let mut bound: F = legs.iter().copied().sum();
+        if min_leg > F::zero() {
+            // every leg has to be longer than min_leg times the shortest possible distance
+            let shortest = legs.iter().cloned().fold(F::infinity(), F::min);
+            let minimum =
+                first.min_distance(second) + second.min_distance(third) + third.min_distance(first);
+            if shortest < min_leg * minimum {
+                return None;
+            }
+            bound = bound.min(shortest / min_leg);
+        }
+        Some(bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::Vincenty;
+    use crate::flight::{AltitudePolicy, Fix, Flight};
+    use crate::point::PointImpl;
+    use assert_approx_eq::assert_approx_eq;
+
+    // deterministic pseudo random track with some structure
+    fn random_track(n: usize) -> Vec<FlatPoint<f64>> {
+        let mut seed: u32 = 42;
+        let mut next = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as f64 / 65_536.0
+        };
+        let mut position = (0.0, 0.0);
+        (0..n)
+            .map(|_| {
+                position.0 += next() * 20.0 - 10.0;
+                position.1 += next() * 20.0 - 10.0;
+                FlatPoint {
+                    x: position.0,
+                    y: position.1,
+                }
+            })
+            .collect()
+    }
+
+    // a route on sea level, only used for the altitude rule
+    fn flat_route(n: usize) -> Vec<PointImpl> {
+        vec![
+            PointImpl {
+                latitude: 0.0,
+                longitude: 0.0,
+                altitude: 0,
+                timestamp: None,
+                engine_noise: None,
+            };
+            n
+        ]
+    }
+
+    fn brute_force(
+        flat_points: &[FlatPoint<f64>],
+        closing: &Closing,
+        min_leg: f64,
+        accept: impl Fn(&[usize]) -> bool,
+    ) -> f64 {
+        let n = flat_points.len();
+        let mut best = 0.0_f64;
+        for a in 0..n {
+            for b in a + 1..n {
+                for c in b + 1..n {
+                    if closing.closes(a, c) && accept(&[a, b, c]) {
+                        best = best.max(triangle_distance(flat_points, a, b, c, min_leg));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn branch_and_bound_matches_brute_force() {
+        let flat_points = random_track(80);
+        let route = flat_route(flat_points.len());
+        for closing_distance in [5.0, 20.0, 50.0] {
+            let closing = Closing::new(
+                &flat_points,
+                &route,
+                closing_distance,
+                AltitudeRule::default(),
+                0.0,
+            );
+            for min_leg in [0.0, FAI_MIN_LEG] {
+                let expected = brute_force(&flat_points, &closing, min_leg, |_| true);
+                let found = find_best_triangle(&flat_points, &closing, min_leg, |_| true)
+                    .map_or(0.0, |triangle| triangle.distance);
+                assert_approx_eq!(found, expected, 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn rejected_triangles_are_skipped() {
+        let flat_points = random_track(60);
+        let route = flat_route(flat_points.len());
+        let closing = Closing::new(&flat_points, &route, 20.0, AltitudeRule::default(), 0.0);
+        let best = find_best_triangle(&flat_points, &closing, FAI_MIN_LEG, |_| true).unwrap();
+        let accept = |path: &[usize]| path != best.path;
+        let expected = brute_force(&flat_points, &closing, FAI_MIN_LEG, accept);
+        let found = find_best_triangle(&flat_points, &closing, FAI_MIN_LEG, accept).unwrap();
+        assert_ne!(found.path, best.path);
+        assert_approx_eq!(found.distance, expected, 1e-3);
+    }
+
+    #[test]
+    fn no_triangle_in_straight_line() {
+        let flat_points: Vec<_> = (0..10)
+            .map(|i| FlatPoint {
+                x: i as f64,
+                y: 0.0,
+            })
+            .collect();
+        let closing = Closing::new(
+            &flat_points,
+            &flat_route(10),
+            100.0,
+            AltitudeRule::default(),
+            0.0,
+        );
+        assert!(find_best_triangle(&flat_points, &closing, FAI_MIN_LEG, |_| true).is_none());
+    }
+
+    fn read_fixes(file: &str) -> Vec<Fix> {
+        Flight::parse(file, AltitudePolicy::Pressure).unwrap().fixes
+    }
+
+    #[test]
+    fn fai_triangle() {
+        let fixes = read_fixes(include_str!("../fixtures/2023-06-17_288167.igc"));
+        let result = optimize(&fixes, 5.0_f32, AltitudeRule::default(), &Vincenty).unwrap();
+        assert_eq!(result.path, [1067, 3707, 6385]);
+        assert!(result.start <= result.path[0] && result.path[2] <= result.stop);
+        assert_approx_eq!(result.distance, 899.64, 0.1);
+        assert!(result
+            .legs
+            .iter()
+            .all(|leg| leg.distance >= FAI_MIN_LEG as f32 * result.distance));
+    }
+
+    #[test]
+    fn fai_triangle_on_long_track() {
+        // more than 37k fixes, the closing and the search must not need a distance matrix
+        let fixes = read_fixes(include_str!("../fixtures/schunk_1000m.igc"));
+        let result = optimize(&fixes, 5.0_f32, AltitudeRule::default(), &Vincenty).unwrap();
+        assert_eq!(result.path, [1441, 16652, 24275]);
+        assert_approx_eq!(result.distance, 648.90, 0.1);
+        assert!(result.start <= result.path[0] && result.path[2] <= result.stop);
+        assert!(result
+            .legs
+            .iter()
+            .all(|leg| leg.distance >= FAI_MIN_LEG as f32 * result.distance));
+    }
+
+    #[test]
+    fn flat_triangle() {
+        let fixes = read_fixes(include_str!("../fixtures/2023-06-17_288167.igc"));
+        let result = optimize_flat(&fixes, 5.0_f32, AltitudeRule::default(), &Vincenty).unwrap();
+        let fai = optimize(&fixes, 5.0_f32, AltitudeRule::default(), &Vincenty).unwrap();
+        assert!(result.distance >= fai.distance);
+        assert_approx_eq!(result.distance, 990.50, 0.1);
+    }
+
+    #[test]
+    fn closing_uses_altitude_rule() {
+        let fixes = read_fixes(include_str!("../fixtures/2023-06-17_288167.igc"));
+        let distance = |altitude| {
+            optimize_flat(&fixes, 5.0_f32, altitude, &Vincenty).map(|result| result.distance)
+        };
+        let default = distance(AltitudeRule::default()).unwrap();
+        assert!(distance(AltitudeRule::Disabled).unwrap() >= default);
+        assert!(distance(AltitudeRule::Relative(0.01)).unwrap() >= default);
+        assert!(distance(AltitudeRule::Fixed(0)).map_or(true, |distance| distance <= default));
+    }
+}
@@ -1,19 +1,27 @@
+use crate::float::Float;
 use crate::point::Point;
-use std::f32;
 
-#[allow(non_snake_case)]
-pub fn vincenty_distance<T: Point>(fix1: &T, fix2: &T) -> f32 {
-    let a = 6378137.;
-    let b = 6356752.314245;
-    let f = 1. / 298.257223563; // WGS-84 ellipsoid params
+pub fn vincenty_distance<F: Float, T: Point>(fix1: &T, fix2: &T) -> Option<F> {
+    vincenty_inverse(fix1, fix2).map(|(distance, _)| distance)
+}
+
+// Return the distance in km and the initial bearing in degrees [0, 360) from fix1 to fix2,
+// or None if the iteration does not converge, which happens for nearly antipodal points
+#[allow(non_snake_case, clippy::excessive_precision)]
+pub fn vincenty_inverse<F: Float, T: Point>(fix1: &T, fix2: &T) -> Option<(F, F)> {
+    let n = F::from_f64;
+    let a = n(6378137.);
+    let b = n(6356752.314245);
+    let f = n(1. / 298.257223563); // WGS-84 ellipsoid params
+    let (zero, one, two, three, four) = (n(0.), n(1.), n(2.), n(3.), n(4.));
 
     // Difference in longitude
 
-    let L = (fix2.longitude() - fix1.longitude()).to_radians();
+    let L = n(fix2.longitude() - fix1.longitude()).to_radians();
     // Reduced latitude (latitude on the auxiliary sphere)
-    let U1 = ((1_f32 - f) * fix1.latitude().to_radians().tan()).atan();
+    let U1 = ((one - f) * n(fix1.latitude()).to_radians().tan()).atan();
     // Reduced latitude (latitude on the auxiliary sphere)
-    let U2 = ((1_f32 - f) * fix2.latitude().to_radians().tan()).atan();
+    let U2 = ((one - f) * n(fix2.latitude()).to_radians().tan()).atan();
     let (sinU1, cosU1) = U1.sin_cos();
     let (sinU2, cosU2) = U2.sin_cos();
     let mut cosSqAlpha;
@@ -25,6 +33,8 @@ pub fn vincenty_distance<T: Point>(fix1: &T, fix2: &T) -> f32 {
     let mut lambda = L;
     let mut lambdaP;
     let mut iterLimit = 100;
+    // 10−12 corresponds to approximately 0.06 mm, f32 only allows for about 10-6
+    let tolerance = n(1e-12).max(n(8.) * F::epsilon());
 
     loop {
         let (sinLambda, cosLambda) = lambda.sin_cos();
@@ -33,35 +43,35 @@ pub fn vincenty_distance<T: Point>(fix1: &T, fix2: &T) -> f32 {
                 * (cosU1 * sinU2 - sinU1 * cosU2 * cosLambda))
             .sqrt();
 
-        if sinSigma == 0_f32 {
-            return 0_f32;
+        if sinSigma == zero {
+            // coincident points
+            return Some((zero, zero));
         }
 
         cosSigma = sinU1 * sinU2 + cosU1 * cosU2 * cosLambda;
         sigma = sinSigma.atan2(cosSigma);
         let sinAlpha = cosU1 * cosU2 * sinLambda / sinSigma;
-        cosSqAlpha = 1_f32 - sinAlpha * sinAlpha;
+        cosSqAlpha = one - sinAlpha * sinAlpha;
 
-        if cosSqAlpha == 0_f32 {
+        if cosSqAlpha == zero {
             // equatorial geodesics require special handling
             // per [Algorithms for geodesics, Charles F. F. Karney](https://arxiv.org/pdf/1109.4448.pdf)
-            cos2SigmaM = 0_f32;
+            cos2SigmaM = zero;
         } else {
-            cos2SigmaM = cosSigma - 2_f32 * sinU1 * sinU2 / cosSqAlpha;
+            cos2SigmaM = cosSigma - two * sinU1 * sinU2 / cosSqAlpha;
         }
 
-        let C = f / 16_f32 * cosSqAlpha * (4_f32 + f * (4_f32 - 3_f32 * cosSqAlpha));
+        let C = f / n(16.) * cosSqAlpha * (four + f * (four - three * cosSqAlpha));
         lambdaP = lambda;
         lambda = L
-            + (1_f32 - C)
+            + (one - C)
                 * f
                 * sinAlpha
                 * (sigma
                     + C * sinSigma
-                        * (cos2SigmaM + C * cosSigma * (-1_f32 + 2_f32 * cos2SigmaM * cos2SigmaM)));
+                        * (cos2SigmaM + C * cosSigma * (-one + two * cos2SigmaM * cos2SigmaM)));
 
-        // 10−12 corresponds to approximately 0.06 mm
-        if (lambda - lambdaP).abs() <= 1e-6 {
+        if (lambda - lambdaP).abs() <= tolerance {
             break;
         }
 
@@ -73,24 +83,70 @@ pub fn vincenty_distance<T: Point>(fix1: &T, fix2: &T) -> f32 {
     }
 
     if iterLimit == 0 {
-        return 0.0;
+        return None;
     }
 
     let uSq = cosSqAlpha * (a * a - b * b) / (b * b);
-    let A =
-        1_f32 + uSq / 16384_f32 * (4096_f32 + uSq * (-768_f32 + uSq * (320_f32 - 175_f32 * uSq)));
-    let B = uSq / 1024_f32 * (256_f32 + uSq * (-128_f32 + uSq * (74_f32 - 47_f32 * uSq)));
+    let A = one + uSq / n(16384.) * (n(4096.) + uSq * (n(-768.) + uSq * (n(320.) - n(175.) * uSq)));
+    let B = uSq / n(1024.) * (n(256.) + uSq * (n(-128.) + uSq * (n(74.) - n(47.) * uSq)));
 
     let deltaSigma = B
         * sinSigma
         * (cos2SigmaM
-            + B / 4_f32
-                * (cosSigma * (-1_f32 + 2_f32 * cos2SigmaM * cos2SigmaM)
-                    - B / 6_f32
+            + B / four
+                * (cosSigma * (-one + two * cos2SigmaM * cos2SigmaM)
+                    - B / n(6.)
                         * cos2SigmaM
-                        * (-3_f32 + 4_f32 * sinSigma * sinSigma)
-                        * (-3_f32 + 4_f32 * cos2SigmaM * cos2SigmaM)));
+                        * (-three + four * sinSigma * sinSigma)
+                        * (-three + four * cos2SigmaM * cos2SigmaM)));
 
     let s = b * A * (sigma - deltaSigma);
-    s / 1000_f32
+
+    let (sinLambda, cosLambda) = lambda.sin_cos();
+    let alpha1 = (cosU2 * sinLambda).atan2(cosU1 * sinU2 - sinU1 * cosU2 * cosLambda);
+    let bearing = (alpha1.to_degrees() + n(360.)) % n(360.);
+
+    Some((s / n(1000.), bearing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::point::PointImpl;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn point(latitude: f64, longitude: f64) -> PointImpl {
+        PointImpl {
+            latitude,
+            longitude,
+            altitude: 0,
+            timestamp: None,
+            engine_noise: None,
+        }
+    }
+
+    #[test]
+    fn double_precision_matches_reference() {
+        // Flinders Peak to Buninyong, the example of Vincenty's paper: 54972.271 m, 306°52'05.37"
+        let flinders_peak = point(
+            -(37. + 57. / 60. + 3.7203 / 3600.),
+            144. + 25. / 60. + 29.5244 / 3600.,
+        );
+        let buninyong = point(
+            -(37. + 39. / 60. + 10.1561 / 3600.),
+            143. + 55. / 60. + 35.3839 / 3600.,
+        );
+        let (distance, bearing): (f64, f64) = vincenty_inverse(&flinders_peak, &buninyong).unwrap();
+        assert_approx_eq!(distance, 54.972271, 1e-6);
+        assert_approx_eq!(bearing, 306. + 52. / 60. + 5.37 / 3600., 1e-5);
+
+        let distance: f32 = vincenty_distance(&flinders_peak, &buninyong).unwrap();
+        assert_approx_eq!(distance, 54.972, 0.01);
+    }
+
+    #[test]
+    fn nearly_antipodal_points_do_not_converge() {
+        let distance: Option<f64> = vincenty_distance(&point(0.0, 0.0), &point(0.5, 179.5));
+        assert_eq!(distance, None);
+    }
 }